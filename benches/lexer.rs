@@ -0,0 +1,85 @@
+//! Compares the instruction interpreter against the compiled bytecode on the
+//! same routines. Run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use rustuck::Instruction::*;
+use rustuck::*;
+
+fn number_routines() -> Vec<Routine<'static>> {
+    let digits: Vec<Instruction> = "0123456789".split_inclusive(|_| true)
+        .flat_map(|d| [If(d), Add("digit")])
+        .collect();
+
+    vec![
+        Routine::new("digits", vec![Block(digits)]),
+        Routine::new("ints", vec![
+            If("digit"), Skip, Else, Cancel,
+            Label("Loop"),
+            Next,
+            If("digit"), Goto("Loop"),
+            Block(vec![Wrap, Back, Add("int")])
+        ]),
+        Routine::new("negatives", vec![
+            If("-"), Next, Else, Cancel,
+            If("int"), Block(vec![Next, Wrap, Back, Add("int"), Add("negInt")])
+        ]),
+        Routine::new("noWs", vec![If("ws"), Delete])
+    ]
+}
+
+fn input(lines: usize) -> String {
+    (0..lines)
+        .map(|i| format!("{} -{} {}{}\n", i, i * 7, i % 13, i * 31))
+        .collect()
+}
+
+fn time(runs: u32, mut run: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..runs {
+        run();
+    }
+    start.elapsed() / runs
+}
+
+fn main() {
+    let all = Lexer::new(number_routines());
+    // `Wrap` and `Delete` remove tokens from the middle of the vector, which
+    // is linear in its length and dominates on large inputs; this set only
+    // tags tokens, so it measures the cost of running the instructions
+    let mut tagging = Lexer::new(number_routines());
+    tagging.rules.retain(|routine| routine.name == "digits");
+
+    for (name, lexer) in [("all routines", &all), ("tagging only", &tagging)] {
+        println!("{}:", name);
+        bench(lexer);
+    }
+}
+
+fn bench(lexer: &Lexer) {
+    let compiled = lexer.compile().expect("the benchmark routines are valid");
+
+    for lines in [100, 1_000, 4_000] {
+        let text = input(lines);
+        let tokens = to_tokens(&text, "bench");
+        let runs = (20_000 / lines as u32).max(3);
+
+        let interpreted = time(runs, || {
+            let code = &mut tokens.clone();
+            lexer.lex(code, &mut ()).unwrap();
+        });
+        let bytecode = time(runs, || {
+            let code = &mut tokens.clone();
+            compiled.lex(code).unwrap();
+        });
+
+        println!(
+            "{:>6} lines ({:>7} bytes): interpreter {:>10.2?}, bytecode {:>10.2?} ({:.1}x)",
+            lines,
+            text.len(),
+            interpreted,
+            bytecode,
+            interpreted.as_secs_f64() / bytecode.as_secs_f64()
+        );
+    }
+}
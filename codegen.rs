@@ -0,0 +1,120 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::*;
+
+impl Grammar {
+    /// Rust source defining `lexer()` and `parser()` functions that build
+    /// this grammar, for `include!` in a crate that depends on `rustuck`.
+    pub fn to_rust(&self) -> String {
+        let (lexer, parser) = (self.lexer(), self.parser());
+        let mut out = String::new();
+        out += "// Generated by rustuck from a grammar file. Do not edit.\n\n";
+        out += "#[allow(dead_code)]\npub fn lexer() -> rustuck::Lexer<'static> {\n";
+        out += "    use rustuck::Instruction::*;\n";
+        out += "    rustuck::Lexer::new(vec![\n";
+        for (i, routine) in lexer.rules.iter().enumerate() {
+            let _ = writeln!(out, "        rustuck::Routine::new({:?}, vec![", routine.name);
+            write_instructions(&mut out, routine.instrs(), 3);
+            out += if i + 1 < lexer.rules.len() { "        ]),\n" } else { "        ])\n" };
+        }
+        out += "    ])\n}\n\n";
+
+        out += "#[allow(dead_code)]\npub fn parser() -> rustuck::Parser<'static> {\n";
+        out += "    use rustuck::Match::*;\n";
+        out += "    rustuck::Parser::new(vec![\n";
+        for (i, rule) in parser.rules().iter().enumerate() {
+            let matches: Vec<String> = rule.matches.iter().map(match_source).collect();
+            let _ = write!(out, "        rustuck::Rule::new(vec![{}], {})", matches.join(", "), strs_source(&rule.tags));
+            if let Some(index) = rule.repeat {
+                let _ = write!(out, ".with_repeat({index})");
+            }
+            if rule.add_all {
+                out += ".with_add_all()";
+            }
+            for context in &rule.context {
+                let _ = write!(out, ".{}({:?})", context.method(), context.tag());
+            }
+            out += if i + 1 < parser.rules().len() { ",\n" } else { "\n" };
+        }
+        out += "    ])";
+        if let Some(goal) = parser.goal {
+            let _ = write!(out, ".with_goal({:?})", goal);
+        }
+        if let Some(operators) = &parser.operators {
+            let _ = write!(out, "\n    .with_operators(rustuck::OperatorTable::new({:?})", operators.operand());
+            for op in operators.operators() {
+                let method = match op.fixity {
+                    Fixity::InfixLeft => "infixl",
+                    Fixity::InfixRight => "infixr",
+                    Fixity::Prefix => "prefix",
+                    Fixity::Postfix => "postfix"
+                };
+                let _ = write!(out, "\n        .{}({}, &[{:?}])", method, op.precedence, op.tag);
+            }
+            out += ")";
+        }
+        out += "\n}\n";
+        out
+    }
+}
+
+fn match_source(m: &Match) -> String {
+    match m {
+        Match::Tag(tag) => format!("Tag({tag:?})"),
+        Match::OneOf(alternatives) => format!("OneOf({})", strs_source(alternatives)),
+        Match::Not(tag) => format!("Not({tag:?})"),
+        Match::Any => "Any".to_string()
+    }
+}
+
+fn strs_source(strs: &[&str]) -> String {
+    let strs: Vec<String> = strs.iter().map(|s| format!("{s:?}")).collect();
+    format!("vec![{}]", strs.join(", "))
+}
+
+fn write_instructions(out: &mut String, instrs: &[Instruction], depth: usize) {
+    let indent = "    ".repeat(depth);
+    for (i, instr) in instrs.iter().enumerate() {
+        let separator = if i + 1 < instrs.len() { "," } else { "" };
+        let source = match instr {
+            Instruction::Block(block) => {
+                let _ = writeln!(out, "{indent}Block(vec![");
+                write_instructions(out, block, depth + 1);
+                let _ = writeln!(out, "{indent}]){separator}");
+                continue;
+            }
+            Instruction::Next => "Next".to_string(),
+            Instruction::If(tag) => format!("If({tag:?})"),
+            Instruction::Else => "Else".to_string(),
+            Instruction::Cancel => "Cancel".to_string(),
+            Instruction::Skip => "Skip".to_string(),
+            Instruction::Back => "Back".to_string(),
+            Instruction::Wrap => "Wrap".to_string(),
+            Instruction::Delete => "Delete".to_string(),
+            Instruction::Add(tag) => format!("Add({tag:?})"),
+            Instruction::Label(label) => format!("Label({label:?})"),
+            Instruction::Goto(label) => format!("Goto({label:?})")
+        };
+        let _ = writeln!(out, "{indent}{source}{separator}");
+    }
+}
+
+/// Compiles a grammar file into Rust source, for use in a build script:
+///
+/// ```no_run
+/// // build.rs
+/// let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("grammar.rs");
+/// rustuck::compile_grammar("grammar.tuck", out).unwrap();
+/// ```
+///
+/// The crate then includes the generated functions with
+/// `include!(concat!(env!("OUT_DIR"), "/grammar.rs"));`. Cargo is told to
+/// run the build script again when the grammar file changes.
+pub fn compile_grammar(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<(), GrammarError> {
+    let input = input.as_ref();
+    println!("cargo:rerun-if-changed={}", input.display());
+    let grammar = Grammar::load(input)?;
+    std::fs::write(output, grammar.to_rust())
+        .map_err(|e| GrammarError::Io { kind: e.kind(), message: e.to_string() })
+}
@@ -0,0 +1,54 @@
+use std::fmt::Display;
+
+/// An error raised while running a lexer or parser on some input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RustuckError {
+    /// A `Goto` named a label that was not defined
+    UndefinedLabel { routine: String, label: String },
+    /// A `Wrap` was executed without the cursor having moved past the token
+    /// the cycle started on
+    WrapUnderflow { routine: String, start_index: usize, index: usize },
+    /// The cursor of a routine pointed outside of the token vector
+    CursorOutOfBounds { routine: String, index: usize, len: usize },
+    /// The parser kept changing the tokens for too many passes, or an
+    /// expression was nested too deeply
+    RecursionLimit { depth: usize },
+    /// Reading the input failed
+    Io { kind: std::io::ErrorKind, message: String },
+    /// An edit replaced a range that is not inside the text or does not fall
+    /// on character boundaries
+    InvalidEdit { start: usize, end: usize, len: usize },
+    /// The Pratt parser found a token where it expected something else
+    UnexpectedToken { expected: String, found: String, line: usize, char: usize },
+    /// The Pratt parser ran out of tokens where it expected more
+    UnexpectedEnd { expected: String },
+    /// The Pratt parser was asked to start past the end of the tokens
+    StartOutOfBounds { index: usize, len: usize }
+}
+
+impl Display for RustuckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RustuckError::UndefinedLabel { routine, label } =>
+                write!(f, "routine {routine}: Goto to undefined label \"{label}\""),
+            RustuckError::WrapUnderflow { routine, start_index, index } =>
+                write!(f, "routine {routine}: Wrap with nothing to wrap (cycle started at token {start_index}, cursor at {index})"),
+            RustuckError::CursorOutOfBounds { routine, index, len } =>
+                write!(f, "routine {routine}: cursor at token {index} is out of bounds ({len} tokens)"),
+            RustuckError::RecursionLimit { depth } =>
+                write!(f, "parser reached its recursion limit at depth {depth}"),
+            RustuckError::Io { message, .. } =>
+                write!(f, "could not read the input: {message}"),
+            RustuckError::InvalidEdit { start, end, len } =>
+                write!(f, "cannot edit bytes {start}..{end} of a text of {len} bytes"),
+            RustuckError::UnexpectedToken { expected, found, line, char } =>
+                write!(f, "expected {expected}, found {found:?} (line {line}, char {char})"),
+            RustuckError::UnexpectedEnd { expected } =>
+                write!(f, "expected {expected}, found the end of the tokens"),
+            RustuckError::StartOutOfBounds { index, len } =>
+                write!(f, "cannot start an expression at token {index} of {len} tokens")
+        }
+    }
+}
+
+impl std::error::Error for RustuckError {}
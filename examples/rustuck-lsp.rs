@@ -0,0 +1,48 @@
+//! A language server for a small language of `name = 123` assignments, one
+//! per line. Run with `cargo run --example rustuck-lsp` and point an editor's
+//! LSP client at the binary; it speaks JSON-RPC over stdin and stdout.
+
+use std::io;
+
+use rustuck::lsp::{LspConfig, Server, SymbolKind};
+use rustuck::Instruction::*;
+use rustuck::*;
+
+fn main() -> io::Result<()> {
+    let letters: Vec<Instruction> = ('a'..='z').chain('A'..='Z').chain(['_'])
+        .map(|c| &*String::leak(c.to_string()))
+        .flat_map(|c| [If(c), Add("letter")])
+        .collect();
+    let digits: Vec<Instruction> = "0123456789".split_inclusive(|_| true)
+        .flat_map(|d| [If(d), Add("digit")])
+        .collect();
+    let run = |tag, wrapped| vec![
+        If(tag), Skip, Else, Cancel,
+        Label("Loop"),
+        Next,
+        If(tag), Goto("Loop"),
+        Block(vec![Wrap, Back, Add(wrapped)])
+    ];
+
+    let lexer = Lexer::new(vec![
+        Routine::new("letters", vec![Block(letters)]),
+        Routine::new("digits", vec![Block(digits)]),
+        Routine::new("names", run("letter", "name")),
+        Routine::new("ints", run("digit", "int")),
+        Routine::new("noSpaces", vec![If(" "), Delete])
+    ]);
+    let rule = |matches: Vec<&'static str>, tag| Rule::new(matches.into_iter().map(Match::from).collect(), vec![tag]);
+    let parser = Parser::new(vec![
+        rule(vec!["name", "="], "target"),
+        rule(vec!["target", "int"], "assignment"),
+        rule(vec!["assignment", "\n"], "line")
+    ]);
+    let config = LspConfig {
+        token_types: vec![("name", "variable"), ("int", "number"), ("=", "operator")],
+        symbols: vec![("assignment", SymbolKind::Variable)],
+        name_tag: Some("name"),
+        goal: Some("line")
+    };
+
+    Server::new(&lexer, &parser, config).run(io::stdin().lock(), io::stdout().lock())
+}
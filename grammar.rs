@@ -0,0 +1,463 @@
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::tags::interned;
+use crate::*;
+use Instruction::*;
+
+// How deeply `Do!` blocks may nest, so that loading and running a grammar
+// can't overflow the stack.
+const MAX_DEPTH: usize = 256;
+
+/// A lexer and parser loaded from the text of a `.tuck` grammar file.
+///
+/// The format is the syntax of the `routine!` and `rule!` macros, one after
+/// the other, with `//` comments. Routines make up the lexer and rules the
+/// parser, each in the order they are written, `goal!("tag")` sets the goal
+/// of the parser, and `operators!` gives it an `OperatorTable`, with a call
+/// for each group of operators as on the table:
+///
+/// ```text
+/// // integers without leading zeros
+/// routine!(
+///     :ints=
+///         If("nonzero") Skip Else Cancel
+///         Label("Loop")
+///         Next
+///         If("digit") Goto("Loop")
+///         Do!(Wrap Back Add("int"))
+/// )
+/// rule!("int" "+" "int" ; "sum")
+/// rule!("sum" ;; "expression")
+/// goal!("expression")
+/// operators!("expression", infixl(6, "+" "-") infixl(7, "*") prefix(9, "-"))
+/// ```
+///
+/// Strings take the escapes of Rust strings. The names and tags of a grammar
+/// are kept in the table of tag names, like every `TagId`, so it does not
+/// borrow the text it was loaded from and holds a `Lexer<'static>` and a
+/// `Parser<'static>`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Grammar {
+    lexer: Lexer<'static>,
+    parser: Parser<'static>
+}
+
+/// Why a grammar could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarError {
+    /// The text is not a grammar; lines and columns start at 1
+    Syntax { line: usize, column: usize, message: String },
+    /// The routines are not valid, as found by `Lexer::validate`
+    Invalid(Vec<ValidationError>),
+    /// Reading the grammar file failed
+    Io { kind: std::io::ErrorKind, message: String }
+}
+
+impl Display for GrammarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GrammarError::Syntax { line, column, message } => write!(f, "{line}:{column}: {message}"),
+            GrammarError::Invalid(errors) => {
+                let errors: Vec<String> = errors.iter().map(ValidationError::to_string).collect();
+                write!(f, "invalid routines: {}", errors.join("; "))
+            }
+            GrammarError::Io { message, .. } => write!(f, "could not read the grammar: {message}")
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+impl Grammar {
+    /// A grammar owning a copy of the lexer and the parser.
+    pub fn new(lexer: &Lexer, parser: &Parser) -> Grammar {
+        let routines = lexer.rules.iter()
+            .map(|routine| Routine::new(interned(routine.name), routine.instrs().iter().map(owned_instruction).collect()));
+        let mut owned = Parser::new(parser.rules().iter().map(owned_rule).collect());
+        owned.goal = parser.goal.map(interned);
+        owned.operators = parser.operators.as_ref().map(|table| {
+            table.operators().iter().fold(OperatorTable::new(interned(table.operand())), |operators, op| {
+                operators.with(op.fixity, op.precedence, &[interned(op.tag)])
+            })
+        });
+        Grammar { lexer: Lexer::new(routines.collect()), parser: owned }
+    }
+
+    pub fn lexer(&self) -> &Lexer<'static> {
+        &self.lexer
+    }
+
+    pub fn parser(&self) -> &Parser<'static> {
+        &self.parser
+    }
+
+    pub fn parse(text: &str) -> Result<Grammar, GrammarError> {
+        let mut reader = Reader { text, pos: 0, depth: 0 };
+        let (mut routines, mut rules, mut goal, mut operators) = (vec![], vec![], None, None);
+        loop {
+            reader.skip();
+            if reader.pos == text.len() {
+                break;
+            }
+            match reader.macro_name()? {
+                "routine" => routines.push(reader.routine()?),
+                "rule" => rules.push(reader.rule()?),
+                "goal" => {
+                    reader.expect("(")?;
+                    goal = Some(reader.string()?);
+                    reader.expect(")")?;
+                }
+                "operators" => operators = Some(reader.operators()?),
+                other => return Err(reader.error(format!("expected routine!, rule!, goal! or operators!, found {other}!")))
+            }
+        }
+
+        let lexer = Lexer::new(routines);
+        lexer.validate().map_err(GrammarError::Invalid)?;
+        let mut parser = Parser::new(rules);
+        parser.goal = goal;
+        parser.operators = operators;
+        Ok(Grammar { lexer, parser })
+    }
+
+    /// Loads a grammar from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Grammar, GrammarError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| GrammarError::Io { kind: e.kind(), message: e.to_string() })?;
+        Grammar::parse(&text)
+    }
+}
+
+// A copy of an instruction whose strings are interned.
+fn owned_instruction(instr: &Instruction) -> Instruction<'static> {
+    match *instr {
+        Block(ref instrs) => Block(instrs.iter().map(owned_instruction).collect()),
+        Next => Next,
+        If(tag) => If(interned(tag)),
+        Else => Else,
+        Cancel => Cancel,
+        Skip => Skip,
+        Back => Back,
+        Wrap => Wrap,
+        Delete => Delete,
+        Add(tag) => Add(interned(tag)),
+        Label(label) => Label(interned(label)),
+        Goto(label) => Goto(interned(label))
+    }
+}
+
+// A copy of a rule whose tags are interned.
+fn owned_rule(rule: &Rule) -> Rule<'static> {
+    let matches = rule.matches.iter().map(|m| match m {
+        Match::Tag(tag) => Match::Tag(interned(tag)),
+        Match::OneOf(alternatives) => Match::OneOf(alternatives.iter().map(|tag| interned(tag)).collect()),
+        Match::Not(tag) => Match::Not(interned(tag)),
+        Match::Any => Match::Any
+    });
+    let mut owned = Rule::new(matches.collect(), rule.tags.iter().map(|tag| interned(tag)).collect());
+    owned.repeat = rule.repeat;
+    owned.add_all = rule.add_all;
+    owned.context = rule.context.iter().map(|context| match *context {
+        Context::FollowedBy(tag) => Context::FollowedBy(interned(tag)),
+        Context::NotFollowedBy(tag) => Context::NotFollowedBy(interned(tag)),
+        Context::PrecededBy(tag) => Context::PrecededBy(interned(tag)),
+        Context::NotPrecededBy(tag) => Context::NotPrecededBy(interned(tag))
+    }).collect();
+    owned
+}
+
+struct Reader<'t> {
+    text: &'t str,
+    pos: usize,
+    // the number of `Do!` blocks being read
+    depth: usize
+}
+
+impl<'t> Reader<'t> {
+    fn rest(&self) -> &'t str {
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, message: impl Into<String>) -> GrammarError {
+        let (line, column) = LineIndex::new(self.text, PositionOptions { one_based: true, ..Default::default() })
+            .position(self.text, self.pos);
+        GrammarError::Syntax { line, column, message: message.into() }
+    }
+
+    // Skips whitespace and comments.
+    fn skip(&mut self) {
+        loop {
+            let trimmed = self.rest().trim_start();
+            self.pos = self.text.len() - trimmed.len();
+            if !trimmed.starts_with("//") {
+                return;
+            }
+            self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        }
+    }
+
+    fn peek(&mut self, literal: &str) -> bool {
+        self.skip();
+        self.rest().starts_with(literal)
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), GrammarError> {
+        if !self.peek(literal) {
+            let found = self.rest().chars().next().map_or("the end".to_string(), |c| format!("{c:?}"));
+            return Err(self.error(format!("expected {literal:?}, found {found}")));
+        }
+        self.pos += literal.len();
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<&'t str, GrammarError> {
+        self.skip();
+        let length = self.rest().find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(self.rest().len());
+        if length == 0 {
+            return Err(self.error("expected a name"));
+        }
+        let start = self.pos;
+        self.pos += length;
+        Ok(&self.text[start..self.pos])
+    }
+
+    // A name followed by `!`, as in `routine!`.
+    fn macro_name(&mut self) -> Result<&'t str, GrammarError> {
+        let start = self.pos;
+        self.ident()?;
+        let end = self.pos;
+        self.expect("!")?;
+        Ok(&self.text[start..end])
+    }
+
+    fn number(&mut self) -> Result<usize, GrammarError> {
+        self.skip();
+        let length = self.rest().find(|c: char| !c.is_ascii_digit()).unwrap_or(self.rest().len());
+        let number = self.rest()[..length].parse().map_err(|_| self.error("expected a number"))?;
+        self.pos += length;
+        Ok(number)
+    }
+
+    // A string, interned.
+    fn string(&mut self) -> Result<&'static str, GrammarError> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(interned(&s));
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some(c @ ('\\' | '"' | '\'')) => c,
+                        Some('u') => {
+                            let digits: String = chars.by_ref().map(|(_, c)| c).take_while(|&c| c != '}').collect();
+                            digits.strip_prefix('{')
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid \\u escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape"))
+                    };
+                    s.push(escaped);
+                }
+                c => s.push(c)
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    fn routine(&mut self) -> Result<Routine<'static>, GrammarError> {
+        self.expect("(")?;
+        self.expect(":")?;
+        let name = interned(self.ident()?);
+        self.expect("=")?;
+        let instrs = self.instructions()?;
+        self.expect(")")?;
+        Ok(Routine::new(name, instrs))
+    }
+
+    // Instructions up to a closing parenthesis.
+    fn instructions(&mut self) -> Result<Vec<Instruction<'static>>, GrammarError> {
+        let mut instrs = vec![];
+        while !self.peek(")") {
+            let start = self.pos;
+            let instr = match self.ident()? {
+                "Next" => Next,
+                "Else" => Else,
+                "Cancel" => Cancel,
+                "Skip" => Skip,
+                "Back" => Back,
+                "Wrap" => Wrap,
+                "Delete" => Delete,
+                name @ ("If" | "Add" | "Label" | "Goto") => {
+                    self.expect("(")?;
+                    let argument = self.string()?;
+                    self.expect(")")?;
+                    match name {
+                        "If" => If(argument),
+                        "Add" => Add(argument),
+                        "Label" => Label(argument),
+                        _ => Goto(argument)
+                    }
+                }
+                "Do" => {
+                    if self.depth == MAX_DEPTH {
+                        self.pos = start;
+                        return Err(self.error(format!("blocks nested more than {MAX_DEPTH} deep")));
+                    }
+                    self.expect("!")?;
+                    self.expect("(")?;
+                    self.depth += 1;
+                    let block = self.instructions();
+                    self.depth -= 1;
+                    let block = block?;
+                    self.expect(")")?;
+                    Block(block)
+                }
+                "TagFrags" => {
+                    self.expect("!")?;
+                    self.expect("(")?;
+                    let tag = self.string()?;
+                    self.expect(",")?;
+                    let mut block = vec![];
+                    while !self.peek(")") {
+                        block.extend([If(self.string()?), Add(tag)]);
+                    }
+                    self.expect(")")?;
+                    Block(block)
+                }
+                other => {
+                    self.pos = start;
+                    return Err(self.error(format!("unknown instruction {other}")));
+                }
+            };
+            instrs.push(instr);
+        }
+        Ok(instrs)
+    }
+
+    fn operators(&mut self) -> Result<OperatorTable<'static>, GrammarError> {
+        self.expect("(")?;
+        let mut operators = OperatorTable::new(self.string()?);
+        self.expect(",")?;
+        while !self.peek(")") {
+            let start = self.pos;
+            let fixity = match self.ident()? {
+                "infixl" => Fixity::InfixLeft,
+                "infixr" => Fixity::InfixRight,
+                "prefix" => Fixity::Prefix,
+                "postfix" => Fixity::Postfix,
+                other => {
+                    self.pos = start;
+                    return Err(self.error(format!("unknown fixity {other}")));
+                }
+            };
+            self.expect("(")?;
+            self.skip();
+            let start = self.pos;
+            let precedence = self.number()?.try_into().map_err(|_| {
+                self.pos = start;
+                self.error("precedence out of range")
+            })?;
+            self.expect(",")?;
+            while !self.peek(")") {
+                operators = operators.with(fixity, precedence, &[self.string()?]);
+            }
+            self.expect(")")?;
+        }
+        self.expect(")")?;
+        Ok(operators)
+    }
+
+    fn rule(&mut self) -> Result<Rule<'static>, GrammarError> {
+        self.expect("(")?;
+        let mut matches = vec![];
+        while !self.peek(";") {
+            matches.push(self.rule_match()?);
+        }
+        if matches.is_empty() {
+            return Err(self.error("a rule needs a tag to match"));
+        }
+        self.expect(";")?;
+        let mut repeat = None;
+        self.skip();
+        if self.rest().starts_with(|c: char| c.is_ascii_digit()) {
+            let start = self.pos;
+            let index = self.number()?;
+            if index >= matches.len() {
+                self.pos = start;
+                return Err(self.error(format!("cannot repeat match {index} of a rule with {} matches", matches.len())));
+            }
+            repeat = Some(index);
+            self.expect(";")?;
+        }
+        let add_all = self.peek(";");
+        if add_all {
+            self.expect(";")?;
+        }
+        let mut tags = vec![];
+        while !self.peek(")") {
+            tags.push(self.string()?);
+        }
+        if tags.is_empty() {
+            return Err(self.error("a rule needs a tag to add"));
+        }
+        self.expect(")")?;
+
+        let mut context = vec![];
+        while self.peek(".") {
+            self.expect(".")?;
+            let start = self.pos;
+            let method = self.ident()?;
+            self.expect("(")?;
+            let tag = self.string()?;
+            self.expect(")")?;
+            context.push(match method {
+                "followed_by" => Context::FollowedBy(tag),
+                "not_followed_by" => Context::NotFollowedBy(tag),
+                "preceded_by" => Context::PrecededBy(tag),
+                "not_preceded_by" => Context::NotPrecededBy(tag),
+                other => {
+                    self.pos = start;
+                    return Err(self.error(format!("unknown rule condition {other}")));
+                }
+            });
+        }
+        let mut rule = Rule::new(matches, tags);
+        rule.repeat = repeat;
+        rule.add_all = add_all;
+        rule.context = context;
+        Ok(rule)
+    }
+
+    // A match of a rule: `"a"`, `"a" | "b"`, `!"a"` or `_`.
+    fn rule_match(&mut self) -> Result<Match<'static>, GrammarError> {
+        if self.peek("_") {
+            self.expect("_")?;
+            return Ok(Match::Any);
+        }
+        if self.peek("!") {
+            self.expect("!")?;
+            return Ok(Match::Not(self.string()?));
+        }
+        let first = self.string()?;
+        if !self.peek("|") {
+            return Ok(Match::Tag(first));
+        }
+        let mut alternatives = vec![first];
+        while self.peek("|") {
+            self.expect("|")?;
+            alternatives.push(self.string()?);
+        }
+        Ok(Match::OneOf(alternatives))
+    }
+}
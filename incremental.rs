@@ -0,0 +1,233 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::tlex::token::SharedStrs;
+use crate::*;
+
+/// A replacement of the bytes in `range` of a text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub replacement: String
+}
+
+/// What an edit recomputed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditSummary {
+    /// The bytes of the new text that were lexed again
+    pub relexed: Range<usize>,
+    /// How many tokens were kept from before the edit
+    pub reused_tokens: usize,
+    /// How many top level parse trees were kept from before the edit
+    pub reused_trees: usize
+}
+
+/// A text together with its lexed tokens and parse trees, which are updated
+/// incrementally when the text is edited.
+///
+/// An edit lexes again only the lines it touches, widened until no old token
+/// crosses their ends, and parses again the new tokens of those lines and of
+/// the top level trees that overlap them, next to the trees that do not. This
+/// gives the same result as lexing and parsing the whole text again as long as
+/// no token of the lexer spans a line break, and the parser reduces the kept
+/// trees the same way it reduces the tokens they were made from.
+pub struct Document<'g> {
+    lexer: &'g Lexer<'g>,
+    parser: &'g Parser<'g>,
+    file: Arc<str>,
+    options: PositionOptions,
+    text: Arc<str>,
+    tokens: Vec<OwnedToken>,
+    trees: Vec<OwnedParseToken>
+}
+
+impl<'g> Document<'g> {
+    pub fn new(
+        lexer: &'g Lexer<'g>,
+        parser: &'g Parser<'g>,
+        file_name: &str,
+        text: &str
+    ) -> Result<Self, RustuckError> {
+        Document::with_options(lexer, parser, file_name, text, PositionOptions::default())
+    }
+
+    /// Makes a document whose tokens report lines and columns as configured.
+    pub fn with_options(
+        lexer: &'g Lexer<'g>,
+        parser: &'g Parser<'g>,
+        file_name: &str,
+        text: &str,
+        options: PositionOptions
+    ) -> Result<Self, RustuckError> {
+        let mut document = Document {
+            lexer,
+            parser,
+            file: file_name.into(),
+            options,
+            text: text.into(),
+            tokens: vec![],
+            trees: vec![]
+        };
+        let code = document.lex(0..text.len(), true)?;
+        let trees = document.parse(code.iter().map(OwnedToken::as_token).map(ParseToken::from).collect())?;
+        document.tokens = code;
+        document.trees = trees;
+        Ok(document)
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn tokens(&self) -> &[OwnedToken] {
+        &self.tokens
+    }
+
+    pub fn trees(&self) -> &[OwnedParseToken] {
+        &self.trees
+    }
+
+    /// Applies an edit to the text, then updates the tokens and trees.
+    pub fn edit(&mut self, edit: &TextEdit) -> Result<EditSummary, RustuckError> {
+        let Range { start, end } = edit.range;
+        let old = self.text.clone();
+        if start > end || end > old.len() || !old.is_char_boundary(start) || !old.is_char_boundary(end) {
+            return Err(RustuckError::InvalidEdit { start, end, len: old.len() });
+        }
+        self.text = format!("{}{}{}", &old[..start], edit.replacement, &old[end..]).into();
+        let delta = edit.replacement.len() as isize - (end - start) as isize;
+        let shift = |offset: usize| offset.checked_add_signed(delta).unwrap();
+
+        // the lines to lex again, in the old text
+        let (mut from, mut to) = (line_start(&old, start), line_end(&old, end));
+        loop {
+            let (new_from, new_to) = (
+                self.tokens.iter()
+                    .find(|t| t.location.start < from && t.location.end > from)
+                    .map_or(from, |t| line_start(&old, t.location.start)),
+                self.tokens.iter()
+                    .find(|t| t.location.start < to && t.location.end > to)
+                    .map_or(to, |t| line_end(&old, t.location.end))
+            );
+            if (new_from, new_to) == (from, to) {
+                break;
+            }
+            (from, to) = (new_from, new_to);
+        }
+        let at_end = to == old.len();
+        let in_region = |t: &Range<usize>| (t.start >= from && t.start < to) || (at_end && t.start == to);
+
+        // tokens
+        let relexed = self.lex(from..shift(to), at_end)?;
+        let old_lines = old[from..to].matches('\n').count();
+        let new_lines = self.text[from..shift(to)].matches('\n').count();
+        let line_delta = new_lines as isize - old_lines as isize;
+
+        let first = self.tokens.iter().position(|t| in_region(&t.location)).unwrap_or(self.tokens.len());
+        let after = self.tokens[first..].iter().position(|t| !in_region(&t.location)).map_or(self.tokens.len(), |i| first + i);
+        let reused_tokens = self.tokens.len() - (after - first);
+        let mut tokens: Vec<OwnedToken> = self.tokens[..first].to_vec();
+        tokens.extend(relexed);
+        tokens.extend(self.tokens[after..].iter().map(|t| {
+            let mut t = t.clone();
+            t.location = shift(t.location.start)..shift(t.location.end);
+            t.line = t.line.checked_add_signed(line_delta).unwrap();
+            t
+        }));
+        for t in &mut tokens {
+            t.source = self.text.clone();
+        }
+
+        // the trees that overlap the lexed lines, and what they span
+        let damaged = |t: &OwnedParseToken| {
+            (t.location.start < to && t.location.end > from) || in_region(&t.location)
+        };
+        let (mut damaged_from, mut damaged_to) = (from, to);
+        for tree in self.trees.iter().filter(|t| damaged(t)) {
+            damaged_from = damaged_from.min(tree.location.start);
+            damaged_to = damaged_to.max(tree.location.end);
+        }
+        let (new_from, new_to) = (damaged_from, shift(damaged_to));
+        let leaf_at_end = damaged_to == old.len();
+
+        let mut code: Vec<ParseToken> = vec![];
+        let mut reused_trees = 0;
+        for tree in self.trees.iter().filter(|t| t.location.end <= damaged_from && !damaged(t)) {
+            code.push(tree.as_parse_token());
+            reused_trees += 1;
+        }
+        for token in tokens.iter().filter(|t| {
+            (t.location.start >= new_from && t.location.start < new_to)
+                || (leaf_at_end && t.location.start == new_to)
+        }) {
+            code.push(ParseToken::from(token.as_token()));
+        }
+        for tree in self.trees.iter().filter(|t| t.location.start >= damaged_to && !damaged(t)) {
+            let mut tree = tree.as_parse_token();
+            shift_tree(&mut tree, &shift, line_delta);
+            code.push(tree);
+            reused_trees += 1;
+        }
+        for pt in &mut code {
+            rebase_tree(pt, &self.text);
+        }
+
+        let trees = self.parse(code)?;
+        self.tokens = tokens;
+        self.trees = trees;
+        Ok(EditSummary { relexed: from..shift(to), reused_tokens, reused_trees })
+    }
+
+    // Lexes a range of the current text, which starts on a line.
+    fn lex(&self, range: Range<usize>, at_end: bool) -> Result<Vec<OwnedToken>, RustuckError> {
+        let line = self.text[..range.start].matches('\n').count();
+        let mut code = to_tokens_with(&self.text[range.clone()], &self.file, self.options);
+        if !at_end {
+            code.pop();
+        }
+        self.lexer.lex(&mut code, &mut ())?;
+
+        Ok(code.into_iter()
+            .map(|t| OwnedToken {
+                location: t.location.start + range.start..t.location.end + range.start,
+                source: self.text.clone(),
+                tags: t.tags,
+                line: t.line + line,
+                char: t.char,
+                file: self.file.clone()
+            })
+            .collect())
+    }
+
+    fn parse(&self, mut code: Vec<ParseToken>) -> Result<Vec<OwnedParseToken>, RustuckError> {
+        self.parser.parse(&mut code, &mut ())?;
+        let shared = &mut SharedStrs::default();
+        shared.share(&self.text);
+        shared.share(&self.file);
+        Ok(code.iter().map(|pt| pt.to_owned_with(shared)).collect())
+    }
+}
+
+fn line_start(text: &str, offset: usize) -> usize {
+    text[..offset].rfind('\n').map_or(0, |i| i + 1)
+}
+
+fn line_end(text: &str, offset: usize) -> usize {
+    text[offset..].find('\n').map_or(text.len(), |i| offset + i + 1)
+}
+
+fn shift_tree(tree: &mut ParseToken, shift: &impl Fn(usize) -> usize, line_delta: isize) {
+    tree.location = shift(tree.location.start)..shift(tree.location.end);
+    tree.line = tree.line.checked_add_signed(line_delta).unwrap();
+    for child in &mut tree.children {
+        shift_tree(child, shift, line_delta);
+    }
+}
+
+// Points a tree borrowed from old owned trees at the new text.
+fn rebase_tree<'a>(tree: &mut ParseToken<'a>, text: &'a str) {
+    tree.body = text;
+    for child in &mut tree.children {
+        rebase_tree(child, text);
+    }
+}
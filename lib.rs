@@ -5,48 +5,96 @@
  * lexer and declarative parser code on sets of text or tokens respectively.
  */
 
-#![allow(unused_macros)]
 pub mod tlex;
 pub mod utah;
-#[macro_use]
-pub mod macros;
+pub mod codegen;
+pub mod error;
+pub mod grammar;
+pub mod incremental;
+pub mod lsp;
+pub mod report;
+pub mod source_map;
+pub mod tags;
+#[cfg(feature = "serde")]
+mod serialize;
+mod macros;
 
+pub use codegen::*;
+pub use error::*;
+pub use grammar::*;
+pub use incremental::*;
+pub use report::*;
+pub use source_map::*;
+pub use tags::*;
+pub use tlex::bytecode::*;
 pub use tlex::lexer::*;
+pub use tlex::observer::*;
+pub use tlex::position::*;
+pub use tlex::stream::*;
 pub use tlex::token::*;
+pub use tlex::validate::*;
+pub use utah::analysis::*;
+pub use utah::diagnostic::*;
+pub use utah::observer::*;
 pub use utah::parse_token::*;
 pub use utah::parser::*;
+pub use utah::pratt::*;
 
 /// Returns a vector of ParseTokens representing a fully lexed and parsed 
-/// string
+/// string, or the first error raised by the lexer or parser
 /// 
 /// # Arguments
 /// 
 /// - lexer - A reference to a lexer that represents the series of routines 
-/// that will be executed on the string
+///   that will be executed on the string
 /// - parser - A reference to a parser that represents the series of rules that
-/// will be executed on the vector of tokens returned from the lexing
+///   will be executed on the vector of tokens returned from the lexing
 /// - code - The string that is input into the lexer
-/// - verbose - Whether to print debug information
-
 pub fn lex_and_parse<'a>(
-    lexer: &'a Lexer, 
-    parser: &'a Parser, 
-    code: &'a str, 
-    verbose: bool
-) -> Vec<ParseToken<'a>> {
+    lexer: &Lexer, 
+    parser: &Parser, 
+    code: &'a str
+) -> Result<Vec<ParseToken<'a>>, RustuckError> {
+    lex_and_parse_observed(lexer, parser, code, &mut (), &mut ())
+}
+
+/// Like `lex_and_parse`, but reports what the lexer and parser are doing to 
+/// the given observers
+/// 
+/// # Arguments
+/// 
+/// - lexer, parser, code - As in `lex_and_parse`
+/// - lex_observer - Receives every event of the lexer
+/// - parse_observer - Receives every event of the parser
+pub fn lex_and_parse_observed<'a>(
+    lexer: &Lexer, 
+    parser: &Parser, 
+    code: &'a str,
+    lex_observer: &mut dyn LexObserver,
+    parse_observer: &mut dyn ParseObserver
+) -> Result<Vec<ParseToken<'a>>, RustuckError> {
     let code = &mut to_tokens(code, "input");
     let lex = lexer;
-    lex.lex(code, verbose);
+    lex.lex(code, lex_observer)?;
     let parse = parser;
     let mut code = to_parse_tokens(code.to_vec());
-    parse.parse(&mut code, verbose);
-    code
+    parse.parse(&mut code, parse_observer)?;
+    Ok(code)
+}
+
+/// Like `lex_and_parse`, but returns trees that hold a shared copy of the 
+/// code instead of borrowing it
+pub fn lex_and_parse_owned(
+    lexer: &Lexer, 
+    parser: &Parser, 
+    code: &str
+) -> Result<Vec<OwnedParseToken>, RustuckError> {
+    Ok(to_owned_parse_tokens(&lex_and_parse(lexer, parser, code)?))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use super::Instruction::*;
 
     macro_rules! number_lexer {
         () => {
@@ -134,11 +182,1049 @@ mod tests {
     #[test]
     fn simple_lexer() {
         print_parse_tokens(lex_and_parse(&number_lexer!(), &pair_parser!(), 
-                INPUT_TEXT, false));
+                INPUT_TEXT).unwrap());
     }
 
     #[test]
     fn output_token_stream() {
         print_tokens(&to_tokens("Hello, world!", "input"));
     }
+
+    #[test]
+    fn unicode_tokens() {
+        let text = "é 日本 🦀";
+        let tokens = to_tokens(text, "input");
+        let contents: Vec<&str> = tokens.iter().map(|t| t.content()).collect();
+        assert_eq!(contents, vec!["é", " ", "日", "本", " ", "🦀", ""]);
+        assert_eq!(tokens[5].location, 10..14);
+        assert_eq!(tokens[3].tags.names().collect::<Vec<_>>(), vec!["本"]);
+        assert!(tokens[4].tags.has("ws"));
+    }
+
+    #[test]
+    fn token_positions() {
+        let tokens = to_tokens("ab\n\ncd", "input");
+        let positions: Vec<(usize, usize)> = tokens.iter().map(|t| (t.line, t.char)).collect();
+        assert_eq!(positions, vec![(0, 0), (0, 1), (0, 2), (1, 0), (2, 0), (2, 1), (2, 2)]);
+
+        let options = PositionOptions { column_unit: ColumnUnit::Utf16, one_based: true };
+        let tokens = to_tokens_with("x\n🦀é", "input", options);
+        assert_eq!((tokens[3].line, tokens[3].char), (2, 3));
+
+        let index = LineIndex::new("x\n🦀é", options);
+        assert_eq!(index.position("x\n🦀é", 6), (2, 3));
+    }
+
+    #[test]
+    fn wrap_keeps_start_position() {
+        let lexer = lexer!(
+            routine!(
+                :words=
+                    If("ws") Cancel
+                    Label("Loop")
+                    Next
+                    If("ws") Skip Else Goto("Loop")
+                    Wrap
+                    Back
+                    Add("word")
+            )
+        );
+        let code = &mut to_tokens("ab\n cd", "input");
+        lexer.lex(code, &mut ()).unwrap();
+        let word = code.iter().find(|t| t.content() == "cd").unwrap();
+        assert_eq!((word.line, word.char), (1, 1));
+        assert!(word.tags.has("word"));
+    }
+
+    #[test]
+    fn lexer_errors() {
+        let code = &mut to_tokens("abc", "input");
+        let lexer = lexer!(routine!(:jump= Goto("Nowhere")));
+        assert_eq!(lexer.lex(code, &mut ()), Err(RustuckError::UndefinedLabel { 
+            routine: "jump".to_string(), 
+            label: "Nowhere".to_string() 
+        }));
+
+        let lexer = lexer!(routine!(:wrap= Wrap));
+        assert!(matches!(lexer.lex(code, &mut ()), Err(RustuckError::WrapUnderflow { .. })));
+
+        // empty routines and blocks, and no tokens at all, do nothing
+        let lexer = lexer!(routine!(:empty=) routine!(:emptyBlock= Do!() Add("x")));
+        lexer.lex(code, &mut ()).unwrap();
+        assert_eq!(code.len(), 4);
+        assert!(code.iter().all(|token| token.tags.has("x")));
+        lexer.lex(&mut vec![], &mut ()).unwrap();
+    }
+
+    #[test]
+    fn combined_tokens() {
+        let code = lex_and_parse(&lexer!(), &parser!(rule!("a" "b" "c" ; "abc")), "abcd").unwrap();
+        // all three tokens are combined, and the ones after are kept
+        assert_eq!(code[0].children.len(), 3);
+        let contents: Vec<&str> = code.iter().map(|t| t.content()).collect();
+        assert_eq!(contents, vec!["abc", "d", ""]);
+    }
+
+    #[test]
+    fn observed_events() {
+        #[derive(Default)]
+        struct Counter {
+            tags_added: usize,
+            wraps: usize,
+            rule_matches: usize,
+            passes: usize
+        }
+
+        impl LexObserver for Counter {
+            fn observe(&mut self, event: &LexEvent<'_>) {
+                match event {
+                    LexEvent::TagAdded { .. } => self.tags_added += 1,
+                    LexEvent::TokensWrapped { .. } => self.wraps += 1,
+                    _ => {}
+                }
+            }
+        }
+
+        impl ParseObserver for Counter {
+            fn observe(&mut self, event: &ParseEvent<'_>) {
+                match event {
+                    ParseEvent::RuleMatched { .. } => self.rule_matches += 1,
+                    ParseEvent::PassStarted { .. } => self.passes += 1,
+                    _ => {}
+                }
+            }
+        }
+
+        let mut lex_counter = Counter::default();
+        let mut parse_counter = Counter::default();
+        lex_and_parse_observed(&number_lexer!(), &pair_parser!(), "12 3 4", 
+            &mut lex_counter, &mut parse_counter).unwrap();
+        // "12" is wrapped, then tagged int and posInt; "3" and "4" are too
+        assert_eq!(lex_counter.wraps, 3);
+        assert!(lex_counter.tags_added >= 6);
+        assert_eq!(parse_counter.rule_matches, 1);
+        // the pass that pairs "12" and "3", then one that changes nothing
+        assert_eq!(parse_counter.passes, 2);
+    }
+
+    #[test]
+    fn validate_routines() {
+        assert_eq!(number_lexer!().validate(), Ok(()));
+
+        let lexer = lexer!(
+            routine!(
+                :broken=
+                    Next
+                    Back
+                    Wrap
+                    Else
+                    Do!(
+                        Goto("Missing")
+                        Label("Twice")
+                        Label("Twice")
+                    )
+                    If("x")
+            )
+        );
+        let kinds: Vec<(Vec<usize>, ValidationErrorKind)> = lexer.validate().unwrap_err()
+            .into_iter()
+            .map(|e| (e.path, e.kind))
+            .collect();
+        assert_eq!(kinds, vec![
+            (vec![2], ValidationErrorKind::WrapUnderflow),
+            (vec![3], ValidationErrorKind::ElseWithoutIf),
+            (vec![4, 0], ValidationErrorKind::UndefinedLabel("Missing".to_string())),
+            (vec![4, 2], ValidationErrorKind::DuplicateLabel("Twice".to_string())),
+            (vec![5], ValidationErrorKind::MissingBody),
+        ]);
+    }
+
+    #[test]
+    fn scoped_labels() {
+        // skips a run of "a"s with a forward jump, and breaks out of the 
+        // block on the first "b" to tag the whole run
+        let lexer = lexer!(
+            routine!(
+                :runs=
+                    If("a") Goto("Loop") Else Cancel
+                    Label("Done")
+                    Wrap
+                    Back
+                    Add("run")
+                    Cancel
+                    Label("Loop")
+                    Next
+                    Do!(
+                        If("b") Goto("Done")
+                        If("a") Goto("Loop")
+                    )
+                    Cancel
+            )
+        );
+        assert_eq!(lexer.validate(), Ok(()));
+        let code = &mut to_tokens("aab", "input");
+        lexer.lex(code, &mut ()).unwrap();
+        assert_eq!(code[0].content(), "aa");
+        assert_eq!(code[0].tags, TagSet::from_iter(["run"]));
+        assert_eq!(code[1].content(), "b");
+
+        let code = &mut to_tokens("aab", "input");
+        lexer.compile().unwrap().lex(code).unwrap();
+        assert_eq!(code[0].content(), "aa");
+        assert_eq!(code[0].tags, TagSet::from_iter(["run"]));
+    }
+
+    #[test]
+    fn compiled_lexer_matches_interpreter() {
+        let lexer = number_lexer!();
+        let interpreted = &mut to_tokens(INPUT_TEXT, "input");
+        lexer.lex(interpreted, &mut ()).unwrap();
+        let compiled = &mut to_tokens(INPUT_TEXT, "input");
+        lexer.compile().unwrap().lex(compiled).unwrap();
+
+        let summary = |code: &[Token]| -> Vec<(String, Vec<String>)> {
+            code.iter()
+                .map(|t| (t.content().to_string(), t.tags.names().map(str::to_string).collect()))
+                .collect()
+        };
+        assert_eq!(summary(interpreted), summary(compiled));
+
+        let lexer = lexer!(routine!(:jump= Goto("Nowhere")));
+        assert!(matches!(lexer.compile(), Err(RustuckError::UndefinedLabel { .. })));
+    }
+
+    #[test]
+    fn interned_tags() {
+        let int = TagId::intern("int");
+        assert_eq!(TagId::intern("int"), int);
+        assert_eq!(TagId::get("int"), Some(int));
+        assert_ne!(TagId::intern("posInt"), int);
+        assert_eq!(int.name(), "int");
+        assert_eq!(TagId::get("never interned"), None);
+
+        let mut tags = TagSet::from_iter(["int", "posInt"]);
+        assert!(!tags.insert("int"));
+        assert!(tags.contains(int));
+        assert!(!tags.has("decimal"));
+        assert!(!tags.has("never interned"));
+        assert_eq!(TagId::get("never interned"), None);
+        assert_eq!(format!("{:?}", tags), "[\"int\", \"posInt\"]");
+    }
+
+    #[test]
+    fn static_grammar_and_owned_trees() {
+        use std::sync::OnceLock;
+
+        static LEXER: OnceLock<Lexer<'static>> = OnceLock::new();
+        static PARSER: OnceLock<Parser<'static>> = OnceLock::new();
+        let lexer = LEXER.get_or_init(|| number_lexer!());
+        let parser = PARSER.get_or_init(|| pair_parser!());
+
+        let trees = {
+            let code = String::from("1 2 3");
+            lex_and_parse_owned(lexer, parser, &code).unwrap()
+        };
+        let pair = std::thread::spawn(move || trees[0].clone()).join().unwrap();
+        assert_eq!(pair.content(), "1 2");
+        assert!(pair.tags.has("pair"));
+        assert_eq!(pair.children[1].content(), "2");
+        assert!(std::sync::Arc::ptr_eq(&pair.source, &pair.children[0].source));
+    }
+
+    #[test]
+    fn streamed_tokens_match_batch() {
+        let lexer = number_lexer!();
+        let batch = &mut to_tokens(INPUT_TEXT, "input");
+        lexer.lex(batch, &mut ()).unwrap();
+
+        let reader = std::io::Cursor::new(INPUT_TEXT);
+        let streamed: Vec<StreamedToken> = StreamLexer::new(&lexer, "input")
+            .with_chunk_size(16)
+            .tokens(reader)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(streamed.len(), batch.len());
+        for (streamed, batch) in streamed.iter().zip(batch.iter()) {
+            assert_eq!(streamed.token.content(), batch.content());
+            assert_eq!(streamed.location(), batch.location);
+            assert_eq!((streamed.token.line, streamed.token.char), (batch.line, batch.char));
+            assert_eq!(streamed.token.tags, batch.tags);
+        }
+
+        let mut pushed = StreamLexer::new(&lexer, "input").with_chunk_size(4);
+        assert!(pushed.push("12 3").unwrap().is_empty());
+        let first_line = pushed.push("4\n5").unwrap();
+        assert_eq!(first_line.iter().map(|t| t.token.content()).collect::<Vec<_>>(), vec!["12", "34"]);
+        assert_eq!(pushed.finish().unwrap()[0].token.line, 1);
+
+        let long_line = "12 é34 5678\n9";
+        let cut: Vec<StreamedToken> = StreamLexer::new(&lexer, "input")
+            .with_chunk_size(1)
+            .with_max_chunk_size(4)
+            .tokens(std::io::Cursor::new(long_line))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let text: String = cut.iter().map(|t| t.token.content()).collect();
+        assert_eq!(text, long_line.replace([' ', '\n'], ""));
+        for token in cut.iter().filter(|t| t.token.line == 0) {
+            let start = token.location().start;
+            assert!(token.token.content().len() <= 4);
+            assert_eq!(token.token.char, long_line[..start].chars().count());
+        }
+    }
+
+    #[test]
+    fn incremental_edits_match_full_parse() {
+        let lexer = lexer!(
+            routine!(
+                :digits=
+                    TagFrags!("digit", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+            )
+            routine!(
+                :ints=
+                    If("digit") Skip Else Cancel
+                    Label("Loop")
+                    Next
+                    If("digit") Goto("Loop")
+                    Do!(Wrap Back Add("int"))
+            )
+            routine!(
+                :noSpaces=
+                    If(" ") Delete
+            )
+        );
+        let parser = parser!(
+            rule!("-" "int" ; "neg")
+            rule!("int" "\n" ; "line")
+            rule!("neg" "\n" ; "line")
+        );
+        fn summary(document: &Document) -> (Vec<String>, Vec<String>) {
+            (
+                document.tokens().iter()
+                    .map(|t| format!("{:?} {:?} {} {}", t.location, t.tags, t.line, t.char))
+                    .collect(),
+                document.trees().iter().map(|t| t.to_string()).collect()
+            )
+        }
+
+        let mut document = Document::new(&lexer, &parser, "input", "12\n- 3\n45\n").unwrap();
+        // only the second line is lexed again; the tokens and trees of the
+        // first and last lines are kept
+        let edit = TextEdit { range: 5..6, replacement: "3".to_string() };
+        assert_eq!(document.edit(&edit).unwrap(), EditSummary { relexed: 3..7, reused_tokens: 4, reused_trees: 2 });
+        let edits = [
+            (6..7, "33"),
+            (0..0, "7\n"),
+            (5..6, ""),
+            (12..12, "8"),
+            (4..5, " "),
+            (0..13, "9\n")
+        ];
+        for (range, replacement) in edits {
+            let edit = TextEdit { range, replacement: replacement.to_string() };
+            let summary_of_edit = document.edit(&edit).unwrap();
+            assert!(summary_of_edit.reused_tokens > 0 || edit.range.start == 0);
+            let full = Document::new(&lexer, &parser, "input", document.text()).unwrap();
+            assert_eq!(summary(&document), summary(&full), "after {:?}", edit);
+        }
+        assert_eq!(document.text(), "9\n");
+
+        let edit = TextEdit { range: 1..10, replacement: String::new() };
+        assert!(matches!(document.edit(&edit), Err(RustuckError::InvalidEdit { .. })));
+    }
+
+    #[test]
+    fn long_chains_of_passes() {
+        // every pass combines one "a" into the "b" after it
+        let parser = parser!(rule!("a" "b" ; "b"));
+        let input = "a".repeat(3_000) + "b";
+        // on a thread, whose stack is smaller than the main one
+        let len = std::thread::spawn(move || {
+            let mut code = to_parse_tokens(to_tokens(&input, "input"));
+            parser.parse(&mut code, &mut ()).map(|()| code.len())
+        }).join().unwrap();
+        assert_eq!(len, Ok(2));
+    }
+
+    #[test]
+    fn combine_keeps_following_tokens() {
+        let parser = parser!(
+            rule!("int" "int" "int" ; "triple")
+        );
+        let code = lex_and_parse(&number_lexer!(), &parser, "1 2 3 4 5").unwrap();
+        let contents: Vec<&str> = code.iter().map(ParseToken::content).collect();
+        assert_eq!(contents[..2], ["1 2 3", "4"]);
+        assert_eq!(code[0].children.len(), 3);
+        assert!(code[1].tags.has("int") && code[2].tags.has("int"));
+    }
+
+    #[test]
+    fn goal_diagnostics() {
+        let lexer = lexer!(
+            routine!(
+                :digits=
+                    TagFrags!("digit", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+                    If("digit") Add("int")
+            )
+            routine!(
+                :noSpaces=
+                    If(" ") Delete
+            )
+        );
+        let parser = parser!(
+            rule!("int" "+" "int" ; "sum")
+            rule!("sum" ";" ; "stmt")
+            rule!("int" ";" ; "stmt")
+        ).with_goal("stmt");
+
+        let code = lex_and_parse(&lexer, &parser, "1 + 2;3 +;4;").unwrap();
+        // all three tokens of the sum are combined into it
+        assert_eq!(code[0].children[0].children.len(), 3);
+        assert_eq!(code[0].content(), "1 + 2;");
+
+        let diagnostics = parser.diagnose(&code);
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!((diagnostic.location.clone(), diagnostic.text), (6..10, "3 +;"));
+        assert_eq!((diagnostic.tokens.clone(), diagnostic.char), (1..4, 6));
+        assert_eq!(diagnostic.near_misses.len(), 1);
+        let miss = &diagnostic.near_misses[0];
+        assert_eq!((miss.rule, miss.matched, &miss.expected), (0, 2, &Match::Tag("int")));
+        assert!(miss.found.as_ref().unwrap().has(";"));
+        assert_eq!(
+            diagnostic.to_string(),
+            "input:0:6: could not parse \"3 +;\"\n  closest: rule 0 (\"int\" \"+\" \"int\") expected \"int\" after 2 token(s), found [\";\"]"
+        );
+
+        let parser = Parser::new(parser.into_rules());
+        assert!(parser.diagnose(&code).is_empty());
+    }
+
+    #[test]
+    fn rendered_reports() {
+        let source = "let x = foo + bar;\nfn f(a) {\n\treturn a;\n}\nlast line\n";
+        let report = Report::new(Severity::Error, "mismatched types", "main.src", source)
+            .with_label(8..11, "this is foo")
+            .with_secondary(14..17, "and this is bar")
+            .with_secondary(4..5, "bound here")
+            .with_note("types must match");
+        assert_eq!(report.render(Style::Plain), [
+            "error: mismatched types",
+            " --> main.src:1:9",
+            "  |",
+            "1 | let x = foo + bar;",
+            "  |     -   ^^^   --- and this is bar",
+            "  |     |   |",
+            "  |     |   this is foo",
+            "  |     bound here",
+            "  |",
+            "  = note: types must match",
+            ""
+        ].join("\n"));
+
+        // a label over several lines, and lines without labels left out
+        let report = Report::new(Severity::Warning, "unused function", "main.src", source)
+            .with_label(19..40, "never called")
+            .with_secondary(43..47, "");
+        assert_eq!(report.to_string(), [
+            "warning: unused function",
+            " --> main.src:2:1",
+            "  |",
+            "2 |   fn f(a) {",
+            "  |  _^",
+            "3 | |     return a;",
+            "  | |______________^ never called",
+            "...",
+            "5 |   last line",
+            "  |    ----",
+            ""
+        ].join("\n"));
+        assert!(report.render(Style::Ansi).contains("\x1b[1;33mwarning\x1b[0m"));
+
+        let lexer = lexer!(
+            routine!(
+                :digits=
+                    TagFrags!("digit", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+                    If("digit") Add("int")
+            )
+        );
+        let parser = parser!(
+            rule!("int" "+" "int" ; "sum")
+        ).with_goal("sum");
+        let code = lex_and_parse(&lexer, &parser, "1+2\n3+").unwrap();
+        let diagnostics = parser.diagnose(&code);
+        assert_eq!(diagnostics[0].report().to_string(), [
+            "error: could not parse these tokens",
+            " --> input:2:1",
+            "  |",
+            "2 | 3+",
+            "  | ^^ not reduced to the goal",
+            "  |",
+            "  = note: closest: rule 0 (\"int\" \"+\" \"int\") expected \"int\" after 2 token(s), found the end of the input",
+            ""
+        ].join("\n"));
+    }
+
+    #[test]
+    fn source_map_positions() {
+        let lexer = lexer!(
+            routine!(
+                :digits=
+                    TagFrags!("digit", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+            )
+            routine!(
+                :ints=
+                    If("digit") Skip Else Cancel
+                    Label("Loop")
+                    Next
+                    If("digit") Goto("Loop")
+                    Do!(Wrap Back Add("int"))
+            )
+        );
+        let parser = parser!(
+            rule!("int" "+" "int" ; "sum")
+        );
+
+        let mut map = SourceMap::new();
+        let a = map.add_file("a.num", "12+3\n4");
+        let b = map.add_file("b.num", "\n56+78");
+        assert_eq!((map.file_id("b.num"), map.name(a), map.source(b)), (Some(b), "a.num", "\n56+78"));
+
+        let tokens = map.lex_all(&lexer).unwrap();
+        let ints: Vec<_> = tokens.iter()
+            .filter(|t| t.tags.has("int"))
+            .map(|t| (t.content(), t.location.clone(), t.file, t.line, t.char))
+            .collect();
+        assert_eq!(ints, [
+            ("12", 0..2, "a.num", 0, 0),
+            ("3", 3..4, "a.num", 0, 3),
+            ("4", 5..6, "a.num", 1, 0),
+            ("56", 8..10, "b.num", 1, 0),
+            ("78", 11..13, "b.num", 1, 3)
+        ]);
+
+        // the end of a file resolves to that file, not to the next one
+        assert_eq!(map.resolve(6), Some(SourcePosition { file: a, line: 1, column: 1 }));
+        assert_eq!(map.resolve(7), Some(SourcePosition { file: b, line: 0, column: 0 }));
+        assert_eq!(map.resolve(12), Some(SourcePosition { file: b, line: 1, column: 4 }));
+        assert_eq!(map.resolve(14), None);
+        assert_eq!(map.localize(8..13), Some((b, 1..6)));
+        assert_eq!(map.localize(5..8), None);
+
+        let mut code = to_parse_tokens(tokens);
+        parser.parse(&mut code, &mut ()).unwrap();
+        let sums: Vec<_> = code.iter()
+            .filter(|pt| pt.tags.has("sum"))
+            .map(|pt| (pt.content(), pt.file))
+            .collect();
+        assert_eq!(sums, [("12+3", "a.num"), ("56+78", "b.num")]);
+
+        let map = map.with_options(PositionOptions { one_based: true, ..Default::default() });
+        assert_eq!(map.resolve(12), Some(SourcePosition { file: b, line: 2, column: 5 }));
+    }
+
+    #[test]
+    fn grammar_files() {
+        let text = r#"
+            // integers, and sums of them
+            routine!(
+                :digits=
+                    TagFrags!("digit", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+            )
+            routine!(
+                :ints=
+                    If("digit") Skip Else Cancel
+                    Label("Loop")
+                    Next
+                    If("digit") Goto("Loop")
+                    Do!(Wrap Back Add("int"))
+            )
+            routine!(:noSpaces= If(" ") Delete)
+
+            rule!("int" "+" "int" ; "sum")
+            rule!("sum" "\n" ; "line") // a line break, escaped
+            rule!("line" ; 0 ;; "lines")
+            goal!("line")
+        "#;
+        let grammar = Grammar::parse(text).unwrap();
+        let lexer = lexer!(
+            routine!(
+                :digits=
+                    TagFrags!("digit", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+            )
+            routine!(
+                :ints=
+                    If("digit") Skip Else Cancel
+                    Label("Loop")
+                    Next
+                    If("digit") Goto("Loop")
+                    Do!(Wrap Back Add("int"))
+            )
+            routine!(:noSpaces= If(" ") Delete)
+        );
+        let parser = parser!(
+            rule!("int" "+" "int" ; "sum")
+            rule!("sum" "\n" ; "line")
+            rule!("line" ; 0 ;; "lines")
+        );
+        assert_eq!(grammar.lexer().rules.iter().map(|r| r.instrs()).collect::<Vec<_>>(), lexer.rules.iter().map(|r| r.instrs()).collect::<Vec<_>>());
+        assert_eq!(format!("{:?}", grammar.parser().rules()), format!("{:?}", parser.rules()));
+        assert_eq!(grammar.parser().goal, Some("line"));
+
+        let input = "12 + 3\n4+56\n7 +";
+        let loaded = lex_and_parse(grammar.lexer(), grammar.parser(), input).unwrap();
+        let built = lex_and_parse(&lexer, &parser, input).unwrap();
+        assert_eq!(format!("{:?}", loaded), format!("{:?}", built));
+        assert_eq!(grammar.parser().diagnose(&loaded)[0].text, "7 +");
+
+        let error = Grammar::parse("routine!(\n  :ints= If(\"digit\") Repeat\n)").unwrap_err();
+        assert_eq!(error, GrammarError::Syntax { line: 2, column: 22, message: "unknown instruction Repeat".to_string() });
+        let error = Grammar::parse("rule!(\"int\" ; 1 ; \"x\")").unwrap_err();
+        assert_eq!(error.to_string(), "1:15: cannot repeat match 1 of a rule with 1 matches");
+        let error = Grammar::parse("operators!(\"x\", infix(1, \"+\"))").unwrap_err();
+        assert_eq!(error.to_string(), "1:17: unknown fixity infix");
+        let deep = format!("routine!(:deep= {}{})", "Do!(".repeat(100_000), ")".repeat(100_000));
+        let error = Grammar::parse(&deep).unwrap_err();
+        assert_eq!(error.to_string(), "1:1041: blocks nested more than 256 deep");
+        let nested = format!("routine!(:nested= {}Next{})", "Do!(".repeat(256), ")".repeat(256));
+        Grammar::parse(&nested).unwrap();
+        let error = Grammar::parse("routine!(:jump= Goto(\"nowhere\"))").unwrap_err();
+        assert!(matches!(error, GrammarError::Invalid(errors) if errors.len() == 1));
+        assert!(matches!(Grammar::load("missing.tuck"), Err(GrammarError::Io { kind: std::io::ErrorKind::NotFound, .. })));
+    }
+
+    mod generated {
+        use crate as rustuck;
+        include!("testdata/sums.rs");
+    }
+
+    macro_rules! int_lexer {
+        () => {
+            lexer!(
+                routine!(:digits= TagFrags!("digit", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9"))
+                routine!(
+                    :ints=
+                        If("digit") Skip Else Cancel
+                        Label("Loop")
+                        Next
+                        If("digit") Goto("Loop")
+                        Do!(Wrap Back Add("int"))
+                )
+                routine!(:noWs= If("ws") Delete)
+            )
+        };
+    }
+
+    // A tree as nested parentheses around the contents of its leaves.
+    fn shape(token: &ParseToken) -> String {
+        if token.children.is_empty() {
+            return token.content().to_string();
+        }
+        let children: Vec<String> = token.children.iter().map(shape).collect();
+        format!("({})", children.join(" "))
+    }
+
+    #[test]
+    fn operator_precedence() {
+        let lexer = int_lexer!();
+        let parser = parser!(
+            rule!("int" ;; "expression")
+            rule!("(" "expression" ")" ; "expression")
+        ).with_operators(OperatorTable::new("expression")
+            .infixl(6, &["+", "-"])
+            .infixl(7, &["*"])
+            .infixr(8, &["^"])
+            .prefix(7, &["-"])
+            .postfix(10, &["!"]));
+        let grammar = Grammar::parse(r#"
+            rule!("int" ;; "expression")
+            rule!("(" "expression" ")" ; "expression")
+            operators!("expression", infixl(6, "+" "-") infixl(7, "*") infixr(8, "^") prefix(7, "-") postfix(10, "!"))
+        "#).unwrap();
+        assert!(grammar.to_rust().contains(".with_operators(rustuck::OperatorTable::new(\"expression\")\n        .infixl(6, &[\"+\"])"));
+
+        for (input, expected) in [
+            ("1 - 2 - 3 * 4", "((1 - 2) - (3 * 4))"),
+            ("2 ^ 3 ^ 2", "(2 ^ (3 ^ 2))"),
+            ("-2 ^ 2", "(- (2 ^ 2))"),
+            ("1 - -2!", "(1 - (- (2 !)))"),
+            ("1 + 2 * (3 - 4)", "(1 + (2 * (( (3 - 4) ))))"),
+            // the "-" is not a prefix, once the parentheses are an expression
+            ("(1 + 2) - 3", "((( (1 + 2) )) - 3)")
+        ] {
+            let parsed = lex_and_parse(&lexer, &parser, input).unwrap();
+            assert_eq!(parsed.len(), 1, "{input}");
+            assert_eq!(shape(&parsed[0]), expected);
+            assert!(parsed[0].tags.has("expression"));
+            let loaded = lex_and_parse(&lexer, grammar.parser(), input).unwrap();
+            assert_eq!(format!("{:?}", loaded), format!("{:?}", parsed));
+        }
+
+        // trees nest only so deep, however many prefix operators there are
+        let deep = "-".repeat(200_000) + "1";
+        assert!(matches!(lex_and_parse(&lexer, &parser, &deep), Err(RustuckError::RecursionLimit { .. })));
+        assert!(lex_and_parse(&lexer, &parser, &("-".repeat(100) + "1")).is_ok());
+    }
+
+    #[test]
+    fn pratt_expressions() {
+        let pratt = PrattParser::new("expression")
+            .atom("int")
+            .group("(", ")")
+            .prefix(9, &["-"])
+            .infixl(6, &["+", "-"])
+            .infixl(7, &["*"])
+            .infixr(8, &["^"])
+            .postfix(10, &["!"])
+            .with_infix("[", 11, |input, left, open| {
+                let index = input.expression(0)?;
+                let close = input.expect("]")?;
+                Ok(input.combine(vec![left, open, index, close]))
+            });
+        let (lexer, none) = (int_lexer!(), parser!());
+        let parse = |input| pratt.parse(lex_and_parse(&lexer, &none, input).unwrap());
+
+        for (input, expected) in [
+            ("1 - 2 - 3 * 4", "((1 - 2) - (3 * 4))"),
+            ("2 ^ 3 ^ 2", "(2 ^ (3 ^ 2))"),
+            ("-2 ^ 2", "((- 2) ^ 2)"),
+            ("(1 + 2)[3]!", "(((( (1 + 2) )) [ 3 ]) !)")
+        ] {
+            let tree = parse(input).unwrap();
+            assert_eq!(shape(&tree), expected);
+            assert!(tree.tags.has("expression"));
+        }
+        assert_eq!(parse("1 + * 2").unwrap_err(), RustuckError::UnexpectedToken {
+            expected: "an expression".to_string(), found: "*".to_string(), line: 0, char: 4
+        });
+        assert_eq!(parse("(1 + 2").unwrap_err().to_string(), "expected \")\", found the end of the tokens");
+        assert_eq!(parse("1 2").unwrap_err().to_string(), "expected the end of the expression, found \"2\" (line 0, char 2)");
+
+        // the trees fit into the rest of the parser
+        let mut code = lex_and_parse(&lexer, &none, "x=1+2*3;").unwrap();
+        pratt.parse_at(&mut code, 2).unwrap();
+        assert_eq!(code.len(), 4);
+        parser!(rule!("x" "=" "expression" ";" ; "statement")).parse(&mut code, &mut ()).unwrap();
+        assert_eq!(code.len(), 1);
+        assert_eq!(shape(&code[0]), "(x = (1 + (2 * 3)) ;)");
+
+        // failing, or starting past the end, leaves the tokens as they were
+        let mut code = lex_and_parse(&lexer, &none, "x=1+;").unwrap();
+        let before = format!("{:?}", code);
+        assert!(matches!(pratt.parse_at(&mut code, 2), Err(RustuckError::UnexpectedToken { .. })));
+        assert!(matches!(pratt.parse_at(&mut code, 9), Err(RustuckError::StartOutOfBounds { index: 9, .. })));
+        assert_eq!(format!("{:?}", code), before);
+
+        let deep = "-".repeat(100_000) + "1";
+        assert!(matches!(parse(&deep), Err(RustuckError::RecursionLimit { .. })));
+    }
+
+    #[test]
+    fn rule_context() {
+        let parser = parser!(
+            rule!("x" ;; "expression").not_followed_by("=")
+            rule!("1" ;; "expression")
+            rule!("x" "=" "expression" ; "assignment")
+            rule!(";" ; "end").preceded_by("assignment")
+        );
+        let mut code = to_parse_tokens(to_tokens("x=1;x;", "input"));
+        parser.parse(&mut code, &mut ()).unwrap();
+        let tags: Vec<Vec<&str>> = code.iter().map(|token| token.tags.names().collect()).collect();
+        assert_eq!(tags, vec![vec!["assignment"], vec!["end"], vec!["x", "expression"], vec![";"], vec![" ", "ws"]]);
+        assert!(!code[0].children[0].tags.has("expression"));
+
+        let grammar = Grammar::parse(r#"rule!("x" ;; "expression").not_followed_by("=") // a use"#).unwrap();
+        assert_eq!(grammar.parser().rules()[0].context, vec![Context::NotFollowedBy("=")]);
+        assert!(grammar.to_rust().contains(r#"rustuck::Rule::new(vec![Tag("x")], vec!["expression"]).with_add_all().not_followed_by("=")"#));
+        assert_eq!(Grammar::parse(r#"rule!("x" ; "y").after("z")"#).unwrap_err().to_string(), "1:18: unknown rule condition after");
+    }
+
+    #[test]
+    fn rule_matches() {
+        let parser = parser!(
+            rule!("1" | "2" ;; "digit")
+            rule!(_ "digit" !"ws" ; "triple")
+        );
+        let mut code = to_parse_tokens(to_tokens("a1b2 c", "input"));
+        parser.parse(&mut code, &mut ()).unwrap();
+        let contents: Vec<&str> = code.iter().map(|token| token.content()).collect();
+        assert_eq!(contents, ["a1b", "2", " ", "c", ""]);
+        assert!(code[0].tags.has("triple") && code[1].tags.has("digit"));
+
+        // the end of the input is not a token for `_` or `!`
+        let parser = parser!(rule!("a" _ ; "pair") rule!("b" !"x" ; "pair"));
+        for input in ["a", "b"] {
+            let mut code = to_parse_tokens(to_tokens(input, "input"));
+            parser.parse(&mut code, &mut ()).unwrap();
+            assert_eq!(code.len(), 2);
+            assert!(!code[0].tags.has("pair"));
+        }
+
+        // matches and repeats can be constants
+        mod tags {
+            pub const A: &str = "a";
+        }
+        const INT: &str = "int";
+        const B: usize = 1;
+        let rule = rule!(INT tags::A "b" ; B ; "c");
+        assert_eq!(rule.matches, vec![Match::Tag("int"), Match::Tag("a"), Match::Tag("b")]);
+        assert_eq!(rule.repeat, Some(1));
+
+        let rule = rule!(("x") "y" | "z" | "w" (Match::Not("v")) ; "u");
+        let matches: Vec<String> = rule.matches.iter().map(|m| m.to_string()).collect();
+        assert_eq!(matches, [r#""x""#, r#""y" | "z" | "w""#, r#"!"v""#]);
+
+        let grammar = Grammar::parse(r#"rule!("1" | "2" _ !"ws" ; "x")"#).unwrap();
+        assert_eq!(grammar.parser().rules()[0].matches, vec![Match::OneOf(vec!["1", "2"]), Match::Any, Match::Not("ws")]);
+        assert!(grammar.to_rust().contains(r#"rustuck::Rule::new(vec![OneOf(vec!["1", "2"]), Any, Not("ws")]"#));
+
+        // an alternative is shadowed like a tag, and a negated tag overlaps
+        let parser = parser!(
+            rule!("int" | "decimal" ; "number")
+            rule!("int" "+" ; "x")
+            rule!(!"ws" ; "token")
+        );
+        let kinds: Vec<(usize, RuleConflictKind)> = parser.analyze().into_iter().map(|c| (c.rule, c.kind)).collect();
+        assert_eq!(kinds, vec![
+            (1, RuleConflictKind::Shadowed { by: 0 }),
+            (2, RuleConflictKind::Overlap { other: 0 }),
+            (2, RuleConflictKind::Overlap { other: 1 })
+        ]);
+    }
+
+    #[test]
+    fn rule_conflicts() {
+        let parser = parser!(
+            rule!("int" "+" "int" ; "sum")
+            rule!("int" "+" "int" "+" "int" ; "sum3")
+            rule!("int" ;; "expression")
+            rule!("expression" "+" "expression" ; "expression")
+            rule!("a" ;; "b")
+            rule!("b" ;; "a")
+            rule!("let" "name" ; "let")
+        );
+        let conflicts = parser.analyze();
+        let kinds: Vec<(usize, RuleConflictKind)> = conflicts.iter().map(|c| (c.rule, c.kind.clone())).collect();
+        assert_eq!(kinds, vec![
+            (1, RuleConflictKind::Shadowed { by: 0 }),
+            (2, RuleConflictKind::Overlap { other: 0 }),
+            (2, RuleConflictKind::Overlap { other: 1 }),
+            (3, RuleConflictKind::Overlap { other: 0 }),
+            (3, RuleConflictKind::Overlap { other: 1 }),
+            (5, RuleConflictKind::RelabelCycle { rules: vec![4, 5] })
+        ]);
+        assert_eq!(conflicts[0].to_string(), "rule 1: never fires, as rule 0 always combines part of its match first");
+        assert_eq!(conflicts[5].to_string(), "rule 5: rules 4, 5 relabel each other in a cycle");
+
+        // a repeated match can be taken any number of times
+        let parser = parser!(
+            rule!("x" ; "z")
+            rule!("x" "y" ; 1 ; "xs")
+            rule!("y" "y" ; 0 ; "ys")
+        );
+        let kinds: Vec<(usize, RuleConflictKind)> = parser.analyze().into_iter().map(|c| (c.rule, c.kind)).collect();
+        assert_eq!(kinds, vec![(1, RuleConflictKind::Shadowed { by: 0 })]);
+
+        // `_` matches whatever token follows
+        let parser = parser!(
+            rule!("a" _ ; "p")
+            rule!("a" "b" ; "q")
+        );
+        let kinds: Vec<(usize, RuleConflictKind)> = parser.analyze().into_iter().map(|c| (c.rule, c.kind)).collect();
+        assert_eq!(kinds, vec![(1, RuleConflictKind::Shadowed { by: 0 })]);
+    }
+
+    #[test]
+    fn generated_grammar() {
+        let grammar = Grammar::load("testdata/sums.tuck").unwrap();
+        // the checked in source is what the grammar generates, and compiles
+        assert_eq!(grammar.to_rust(), include_str!("testdata/sums.rs"));
+
+        let (lexer, parser) = (generated::lexer(), generated::parser());
+        let input = "12 + 3\n4+56\n7 +";
+        let loaded = lex_and_parse(grammar.lexer(), grammar.parser(), input).unwrap();
+        let built = lex_and_parse(&lexer, &parser, input).unwrap();
+        assert_eq!(format!("{:?}", loaded), format!("{:?}", built));
+        assert_eq!(parser.goal, Some("line"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let grammar = Grammar::load("testdata/sums.tuck").unwrap();
+        let json = serde_json::to_string(&grammar).unwrap();
+        let read: Grammar = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.to_rust(), grammar.to_rust());
+        assert_eq!(serde_json::to_string(&read).unwrap(), json);
+        let routines = serde_json::to_string(grammar.lexer()).unwrap();
+        let lexer: Lexer = serde_json::from_str(&routines).unwrap();
+        assert_eq!(lexer.rules[0].instrs(), grammar.lexer().rules[0].instrs());
+
+        let parsed = lex_and_parse(read.lexer(), read.parser(), "1+2\n").unwrap();
+        assert_eq!(serde_json::to_value(&parsed[0]).unwrap(), serde_json::json!({
+            "start": 0, "end": 4, "line": 0, "char": 0, "file": "input",
+            "tags": ["line", "lines"],
+            "children": [
+                {
+                    "start": 0, "end": 3, "line": 0, "char": 0, "file": "input",
+                    "tags": ["sum"],
+                    "children": [
+                        { "start": 0, "end": 1, "line": 0, "char": 0, "file": "input", "tags": ["int"], "children": [] },
+                        { "start": 1, "end": 2, "line": 0, "char": 1, "file": "input", "tags": ["+"], "children": [] },
+                        { "start": 2, "end": 3, "line": 0, "char": 2, "file": "input", "tags": ["int"], "children": [] }
+                    ]
+                },
+                { "start": 3, "end": 4, "line": 0, "char": 3, "file": "input", "tags": ["\n", "ws"], "children": [] }
+            ]
+        }));
+    }
+
+    #[test]
+    fn language_server_session() {
+        use lsp::json::Json;
+
+        let lexer = lexer!(
+            routine!(
+                :digits=
+                    TagFrags!("digit", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+            )
+            routine!(
+                :ints=
+                    If("digit") Skip Else Cancel
+                    Label("Loop")
+                    Next
+                    If("digit") Goto("Loop")
+                    Do!(Wrap Back Add("int"))
+            )
+            routine!(
+                :noSpaces=
+                    If(" ") Delete
+            )
+        );
+        let parser = parser!(
+            rule!("-" "int" ; "neg")
+            rule!("int" "\n" ; "line")
+            rule!("neg" "\n" ; "line")
+        );
+        let config = lsp::LspConfig {
+            token_types: vec![("int", "number"), ("-", "operator")],
+            symbols: vec![("line", lsp::SymbolKind::Variable)],
+            name_tag: None,
+            goal: Some("line")
+        };
+
+        let uri = "file:///input.txt";
+        let script = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#.to_string(),
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#.to_string(),
+            format!(r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{uri}","version":1,"text":"12\n- 3\n4"}}}}}}"#),
+            format!(r#"{{"jsonrpc":"2.0","id":2,"method":"textDocument/semanticTokens/full","params":{{"textDocument":{{"uri":"{uri}"}}}}}}"#),
+            format!(r#"{{"jsonrpc":"2.0","method":"textDocument/didChange","params":{{"textDocument":{{"uri":"{uri}","version":2}},"contentChanges":[{{"range":{{"start":{{"line":2,"character":1}},"end":{{"line":2,"character":1}}}},"text":"\n"}}]}}}}"#),
+            format!(r#"{{"jsonrpc":"2.0","id":3,"method":"textDocument/documentSymbol","params":{{"textDocument":{{"uri":"{uri}"}}}}}}"#),
+            r#"{"jsonrpc":"2.0","id":4,"method":"shutdown"}"#.to_string(),
+            r#"{"jsonrpc":"2.0","method":"exit"}"#.to_string()
+        ];
+        let mut input = vec![];
+        for message in &script {
+            input.extend(format!("Content-Length: {}\r\n\r\n{}", message.len(), message).bytes());
+        }
+        let mut output = vec![];
+        lsp::Server::new(&lexer, &parser, config).run(&input[..], &mut output).unwrap();
+
+        let mut output = &output[..];
+        let mut replies = vec![];
+        while let Some(message) = lsp::read_message(&mut output).unwrap() {
+            replies.push(Json::parse(&message).unwrap());
+        }
+        assert_eq!(replies.len(), 6);
+
+        let legend = replies[0].at(&["result", "capabilities", "semanticTokensProvider", "legend", "tokenTypes"]);
+        assert_eq!(legend.unwrap().to_string(), r#"["number","operator"]"#);
+
+        // the last line has no line break, so it is left unparsed
+        let diagnostics = replies[1].at(&["params", "diagnostics"]).and_then(Json::as_array).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].at(&["range", "start", "line"]).and_then(Json::as_u64), Some(2));
+
+        let data = replies[2].at(&["result", "data"]).unwrap();
+        assert_eq!(data.to_string(), "[0,0,2,0,0,1,0,1,1,0,0,2,1,0,0,1,0,1,0,0]");
+
+        let diagnostics = replies[3].at(&["params", "diagnostics"]).and_then(Json::as_array).unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(replies[3].at(&["params", "version"]).and_then(Json::as_u64), Some(2));
+
+        let symbols = replies[4].get("result").and_then(Json::as_array).unwrap();
+        let names: Vec<_> = symbols.iter().map(|s| s.get("name").and_then(Json::as_str).unwrap()).collect();
+        assert_eq!(names, ["12", "- 3", "4"]);
+        assert_eq!(symbols[1].at(&["range", "end", "line"]).and_then(Json::as_u64), Some(2));
+    }
+
+    #[test]
+    fn language_server_limits() {
+        use lsp::json::Json;
+
+        let deep = "[".repeat(100_000) + &"]".repeat(100_000);
+        assert!(Json::parse(&deep).is_err());
+        assert!(Json::parse(&("[".repeat(100) + &"]".repeat(100))).is_ok());
+        assert!(Json::parse("\"\\é\"").is_err());
+        assert!(Json::parse("\"\\x\"").is_err());
+        assert!(Json::parse("\"\\u+041\"").is_err());
+        assert_eq!(Json::parse("\"\\\"\\/\\u00e9é\"").unwrap().as_str(), Some("\"/éé"));
+
+        let huge = "Content-Length: 1000000000000\r\n\r\n{}";
+        let error = lsp::read_message(&mut huge.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        let lexer = number_lexer!();
+        let parser = pair_parser!();
+        let config = lsp::LspConfig::default();
+        let uri = "file:///input.txt";
+        let script = [
+            format!(r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{uri}","version":1,"text":"1 2"}}}}}}"#),
+            format!(r#"{{"jsonrpc":"2.0","method":"textDocument/didChange","params":{{"textDocument":{{"uri":"{uri}","version":2}},"contentChanges":[{{"range":{{"start":{{"line":0,"character":0}},"end":{{"line":0,"character":0}}}},"text":"3 "}},{{"range":{{"start":{{"line":0,"character":3}},"end":{{"line":0,"character":1}}}},"text":""}}]}}}}"#),
+            format!(r#"{{"jsonrpc":"2.0","id":1,"method":"textDocument/documentSymbol","params":{{"textDocument":{{"uri":"{uri}"}}}}}}"#)
+        ];
+        let mut input = vec![];
+        for message in &script {
+            input.extend(format!("Content-Length: {}\r\n\r\n{}", message.len(), message).bytes());
+        }
+        let mut output = vec![];
+        lsp::Server::new(&lexer, &parser, config).run(&input[..], &mut output).unwrap();
+
+        let mut output = &output[..];
+        let mut replies = vec![];
+        while let Some(message) = lsp::read_message(&mut output).unwrap() {
+            replies.push(Json::parse(&message).unwrap());
+        }
+        // the failed change is reported, and the document is kept as it was
+        let diagnostics = replies[1].at(&["params", "diagnostics"]).and_then(Json::as_array).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(replies[1].at(&["params", "version"]).and_then(Json::as_u64), Some(1));
+        assert!(replies[2].get("result").is_some());
+    }
+
+    #[cfg(feature = "unicode-width")]
+    #[test]
+    fn wide_characters_in_reports() {
+        let source = "名前 = 値;\n";
+        let report = Report::new(Severity::Error, "unknown value", "main.src", source)
+            .with_label(9..12, "not defined");
+        assert_eq!(report.to_string(), [
+            "error: unknown value",
+            " --> main.src:1:6",
+            "  |",
+            "1 | 名前 = 値;",
+            "  |        ^^ not defined",
+            ""
+        ].join("\n"));
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn grapheme_tokens() {
+        let tokens = to_grapheme_tokens("e\u{301}x", "input");
+        assert_eq!(tokens[0].content(), "e\u{301}");
+        assert_eq!(tokens[1].content(), "x");
+    }
 }
@@ -0,0 +1,436 @@
+pub mod json;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::*;
+use json::Json;
+
+// The longest message body read, so that a header can't make the server
+// allocate without bound.
+const MAX_MESSAGE_LENGTH: usize = 64 * 1024 * 1024;
+
+/// The kinds of symbols an LSP client knows, with their protocol numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    File = 1,
+    Module,
+    Namespace,
+    Package,
+    Class,
+    Method,
+    Property,
+    Field,
+    Constructor,
+    Enum,
+    Interface,
+    Function,
+    Variable,
+    Constant,
+    String,
+    Number,
+    Boolean,
+    Array,
+    Object,
+    Key,
+    Null,
+    EnumMember,
+    Struct,
+    Event,
+    Operator,
+    TypeParameter
+}
+
+/// What a language server reports about the tokens and trees of a grammar.
+#[derive(Debug, Clone, Default)]
+pub struct LspConfig<'g> {
+    /// Tags and the semantic token types of the lexed tokens that have them.
+    /// The first tag a token has decides its type.
+    pub token_types: Vec<(&'g str, &'g str)>,
+    /// Tags and the kinds of the document symbols made from the parse trees
+    /// that have them
+    pub symbols: Vec<(&'g str, SymbolKind)>,
+    /// The tag of the token inside a symbol that names it. Symbols without
+    /// one are named after the first line of their content.
+    pub name_tag: Option<&'g str>,
+    /// The tag of a complete top level tree. Runs of top level trees without
+    /// it are reported as errors with the rules closest to matching them, as
+    /// `Parser::diagnose` does; without a goal, tokens the parser did not
+    /// combine with anything are.
+    pub goal: Option<&'g str>
+}
+
+/// A language server for documents lexed and parsed with a Rustuck grammar,
+/// speaking JSON-RPC over a reader and a writer, usually stdin and stdout.
+///
+/// It serves semantic tokens, document symbols and diagnostics, and keeps
+/// each open document as a `Document`, so incremental changes are lexed and
+/// parsed again only where they touch it.
+pub struct Server<'g> {
+    lexer: &'g Lexer<'g>,
+    parser: &'g Parser<'g>,
+    config: LspConfig<'g>,
+    documents: HashMap<String, (Document<'g>, Option<u64>)>,
+    shut_down: bool
+}
+
+impl<'g> Server<'g> {
+    pub fn new(lexer: &'g Lexer<'g>, parser: &'g Parser<'g>, config: LspConfig<'g>) -> Self {
+        Server { lexer, parser, config, documents: HashMap::new(), shut_down: false }
+    }
+
+    /// Serves messages until the client exits or closes the input.
+    pub fn run(&mut self, mut reader: impl BufRead, mut writer: impl Write) -> io::Result<()> {
+        while let Some(message) = read_message(&mut reader)? {
+            let message = match Json::parse(&message) {
+                Ok(message) => message,
+                Err(e) => {
+                    let error = error_response(Json::Null, -32700, &e);
+                    write_message(&mut writer, &error)?;
+                    continue;
+                }
+            };
+            let method = message.get("method").and_then(Json::as_str).unwrap_or_default();
+            let params = message.get("params").unwrap_or(&Json::Null);
+
+            match message.get("id") {
+                Some(id) => {
+                    let response = match self.request(method, params) {
+                        Ok(result) => Json::object(vec![
+                            ("jsonrpc", "2.0".into()),
+                            ("id", id.clone()),
+                            ("result", result)
+                        ]),
+                        Err((code, text)) => error_response(id.clone(), code, &text)
+                    };
+                    write_message(&mut writer, &response)?;
+                }
+                None if method == "exit" => return Ok(()),
+                None => {
+                    for notification in self.notification(method, params) {
+                        write_message(&mut writer, &notification)?;
+                    }
+                }
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        if self.shut_down {
+            return Err((-32600, "the server was shut down".to_string()));
+        }
+        let document = || {
+            let uri = params.at(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or_default();
+            self.documents.get(uri)
+                .map(|(document, _)| document)
+                .ok_or_else(|| (-32602, format!("unknown document {uri}")))
+        };
+        match method {
+            "initialize" => Ok(self.capabilities()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/semanticTokens/full" =>
+                Ok(Json::object(vec![("data", Json::Array(self.semantic_tokens(document()?)))])),
+            "textDocument/documentSymbol" => {
+                let document = document()?;
+                let index = LineIndex::new(document.text(), utf16());
+                Ok(Json::Array(document.trees().iter()
+                    .flat_map(|tree| self.symbols(tree, document.text(), &index))
+                    .collect()))
+            }
+            _ => Err((-32601, format!("unsupported method {method}")))
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params.at(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or_default().to_string();
+        let version = params.at(&["textDocument", "version"]).and_then(Json::as_u64);
+        let result = match method {
+            "textDocument/didOpen" => {
+                let text = params.at(&["textDocument", "text"]).and_then(Json::as_str).unwrap_or_default();
+                self.open(&uri, text, version)
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").and_then(Json::as_array).unwrap_or_default();
+                self.change(&uri, changes, version)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, None, vec![])];
+            }
+            _ => return vec![]
+        };
+        let diagnostics = match result {
+            Ok(()) => self.diagnostics(&uri),
+            // the last document that could be lexed and parsed is kept
+            Err(e) => vec![diagnostic(((0, 0), (0, 0)), &e.to_string())]
+        };
+        // the version of the document kept, which a failed change leaves as
+        // it was
+        let version = self.documents.get(&uri).and_then(|(_, known)| *known);
+        vec![publish_diagnostics(&uri, version, diagnostics)]
+    }
+
+    fn open(&mut self, uri: &str, text: &str, version: Option<u64>) -> Result<(), RustuckError> {
+        let document = Document::with_options(self.lexer, self.parser, uri, text, utf16())?;
+        self.documents.insert(uri.to_string(), (document, version));
+        Ok(())
+    }
+
+    fn change(&mut self, uri: &str, changes: &[Json], version: Option<u64>) -> Result<(), RustuckError> {
+        let before = self.documents.get(uri).map(|(document, known)| (document.text().to_string(), *known));
+        if let Err(e) = self.apply(uri, changes, version) {
+            // a failed edit may have changed the text without its trees, so
+            // the document is opened again as it was
+            if let Some((text, known)) = before {
+                self.open(uri, &text, known)?;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, uri: &str, changes: &[Json], version: Option<u64>) -> Result<(), RustuckError> {
+        for change in changes {
+            let text = change.get("text").and_then(Json::as_str).unwrap_or_default();
+            let (Some(range), Some((document, _))) = (change.get("range"), self.documents.get_mut(uri)) else {
+                self.open(uri, text, version)?;
+                continue;
+            };
+            let index = LineIndex::new(document.text(), utf16());
+            let offset = |key| {
+                let line = range.at(&[key, "line"]).and_then(Json::as_u64).unwrap_or_default();
+                let character = range.at(&[key, "character"]).and_then(Json::as_u64).unwrap_or_default();
+                index.offset(document.text(), line as usize, character as usize)
+            };
+            let edit = TextEdit { range: offset("start")..offset("end"), replacement: text.to_string() };
+            document.edit(&edit)?;
+        }
+        if let Some((_, known)) = self.documents.get_mut(uri) {
+            *known = version;
+        }
+        Ok(())
+    }
+
+    // The semantic token types, in the order of their first tag.
+    fn legend(&self) -> Vec<&'g str> {
+        let mut types = vec![];
+        for &(_, token_type) in &self.config.token_types {
+            if !types.contains(&token_type) {
+                types.push(token_type);
+            }
+        }
+        types
+    }
+
+    fn capabilities(&self) -> Json {
+        Json::object(vec![
+            ("capabilities", Json::object(vec![
+                ("textDocumentSync", Json::object(vec![
+                    ("openClose", true.into()),
+                    ("change", 2.into())
+                ])),
+                ("semanticTokensProvider", Json::object(vec![
+                    ("legend", Json::object(vec![
+                        ("tokenTypes", Json::Array(self.legend().into_iter().map(Json::from).collect())),
+                        ("tokenModifiers", Json::Array(vec![]))
+                    ])),
+                    ("full", true.into())
+                ])),
+                ("documentSymbolProvider", true.into())
+            ])),
+            ("serverInfo", Json::object(vec![("name", "rustuck-lsp".into())]))
+        ])
+    }
+
+    // The index of a token's type in the legend.
+    fn token_type(&self, tags: &TagSet) -> Option<usize> {
+        let &(_, token_type) = self.config.token_types.iter().find(|(tag, _)| tags.has(tag))?;
+        self.legend().iter().position(|&t| t == token_type)
+    }
+
+    // Semantic tokens as relative line, relative start, length, type and
+    // modifiers. Tokens that span lines are split into one per line.
+    fn semantic_tokens(&self, document: &Document) -> Vec<Json> {
+        let mut data = vec![];
+        let (mut last_line, mut last_start) = (0, 0);
+        for token in document.tokens() {
+            let Some(token_type) = self.token_type(&token.tags) else {
+                continue;
+            };
+            let (mut line, mut start) = (token.line, token.char);
+            for (i, piece) in token.content().split('\n').enumerate() {
+                if i > 0 {
+                    (line, start) = (line + 1, 0);
+                }
+                let length = ColumnUnit::Utf16.width(piece.strip_suffix('\r').unwrap_or(piece));
+                if length == 0 {
+                    continue;
+                }
+                let relative_start = if line == last_line { start - last_start } else { start };
+                data.extend([line - last_line, relative_start, length, token_type, 0].map(Json::from));
+                (last_line, last_start) = (line, start);
+            }
+        }
+        data
+    }
+
+    fn symbols(&self, tree: &OwnedParseToken, text: &str, index: &LineIndex) -> Vec<Json> {
+        let children: Vec<Json> = tree.children.iter()
+            .flat_map(|child| self.symbols(child, text, index))
+            .collect();
+        let Some(&(tag, kind)) = self.config.symbols.iter().find(|(tag, _)| tree.tags.has(tag)) else {
+            return children;
+        };
+
+        let name = self.config.name_tag
+            .and_then(|name_tag| find_descendant(tree, name_tag))
+            .map(|named| named.content())
+            .unwrap_or_else(|| tree.content().lines().next().unwrap_or_default())
+            .trim();
+        let name = if name.is_empty() { tag } else { name };
+        let range = range(tree, text, index);
+        let selection = self.config.name_tag
+            .and_then(|name_tag| find_descendant(tree, name_tag))
+            .map_or(range, |named| self::range(named, text, index));
+        vec![Json::object(vec![
+            ("name", name.into()),
+            ("kind", (kind as usize).into()),
+            ("range", range_json(range)),
+            ("selectionRange", range_json(selection)),
+            ("children", Json::Array(children))
+        ])]
+    }
+
+    fn diagnostics(&self, uri: &str) -> Vec<Json> {
+        let Some((document, _)) = self.documents.get(uri) else {
+            return vec![];
+        };
+        let text = document.text();
+        let index = LineIndex::new(text, utf16());
+        if let Some(goal) = self.config.goal {
+            let trees: Vec<ParseToken> = document.trees().iter().map(OwnedParseToken::as_parse_token).collect();
+            return self.parser.diagnose_against(&trees, goal).iter()
+                .map(|d| {
+                    let mut message = format!("could not parse {:?}", d.text);
+                    for miss in &d.near_misses {
+                        message += &format!("\nclosest: {}", miss);
+                    }
+                    diagnostic(((d.line, d.char), index.position(text, d.location.end)), &message)
+                })
+                .collect();
+        }
+        document.trees().iter()
+            .filter(|tree| tree.children.is_empty() && !tree.location.is_empty() && !tree.tags.has("ws"))
+            .map(|tree| {
+                let tags: Vec<&str> = tree.tags.names().collect();
+                let message = format!("could not parse {:?} (tags {})", tree.content(), tags.join(", "));
+                diagnostic(range(tree, text, &index), &message)
+            })
+            .collect()
+    }
+}
+
+type LspRange = ((usize, usize), (usize, usize));
+
+fn utf16() -> PositionOptions {
+    PositionOptions { column_unit: ColumnUnit::Utf16, one_based: false }
+}
+
+fn find_descendant<'t>(tree: &'t OwnedParseToken, tag: &str) -> Option<&'t OwnedParseToken> {
+    tree.children.iter().find_map(|child| {
+        if child.tags.has(tag) { Some(child) } else { find_descendant(child, tag) }
+    })
+}
+
+fn range(tree: &OwnedParseToken, text: &str, index: &LineIndex) -> LspRange {
+    ((tree.line, tree.char), index.position(text, tree.location.end))
+}
+
+fn range_json(((start_line, start_char), (end_line, end_char)): LspRange) -> Json {
+    let position = |line: usize, character: usize| Json::object(vec![
+        ("line", line.into()),
+        ("character", character.into())
+    ]);
+    Json::object(vec![
+        ("start", position(start_line, start_char)),
+        ("end", position(end_line, end_char))
+    ])
+}
+
+fn diagnostic(range: LspRange, message: &str) -> Json {
+    Json::object(vec![
+        ("range", range_json(range)),
+        ("severity", 1.into()),
+        ("source", "rustuck".into()),
+        ("message", message.into())
+    ])
+}
+
+fn publish_diagnostics(uri: &str, version: Option<u64>, diagnostics: Vec<Json>) -> Json {
+    let mut params = vec![("uri", uri.into()), ("diagnostics", Json::Array(diagnostics))];
+    if let Some(version) = version {
+        params.push(("version", (version as usize).into()));
+    }
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        ("params", Json::object(params))
+    ])
+}
+
+fn error_response(id: Json, code: i64, message: &str) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id),
+        ("error", Json::object(vec![
+            ("code", Json::Number(code as f64)),
+            ("message", message.into())
+        ]))
+    ])
+}
+
+/// Reads one message framed by a `Content-Length` header, or `None` at the
+/// end of the input.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.unwrap_or_default();
+    if length > MAX_MESSAGE_LENGTH {
+        let message = format!("Content-Length {length} is over the limit of {MAX_MESSAGE_LENGTH}");
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes a message framed by a `Content-Length` header.
+pub fn write_message(writer: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
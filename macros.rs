@@ -1,131 +1,162 @@
+// The macros name everything through `$crate`, and `routine!` and `Do!` read
+// their instructions one at a time instead of as expressions, so a crate
+// using them needs no imports besides the macros themselves. An instruction
+// in parentheses is taken as an expression, e.g. a variable holding one.
 
+/// A block that adds `$tag` to the current token if it is any of the
+/// fragments.
+#[macro_export]
 macro_rules! TagFrags {
     ($tag:expr, $($frag:expr)*) => {
-        Block(vec![
+        $crate::Instruction::Block(::std::vec![
             $(
-                If($frag), Add($tag),
+                $crate::Instruction::If($frag), $crate::Instruction::Add($tag),
             )*
         ])
     };
 }
 
+/// A block of instructions.
+#[macro_export]
 macro_rules! Do {
-    ($($instr:expr)*) => {
-        Block(vec![
-            $(
-                $instr,
-            )*
-        ])
+    ($($instr:tt)*) => {
+        $crate::Instruction::Block($crate::__instructions!([] $($instr)*))
     };
 }
 
-macro_rules! routine {
-    (:$name:ident= $($instr:expr)*) => {
-        {
-            let rule_instrs : Vec<Instruction> = vec![
-                $(
-                    $instr,
-                )*
-            ];
+// Turns `Next If("x") Do!(...)` into a `Vec` of instructions, one at a time.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __instructions {
+    ([$($done:expr),*]) => {
+        ::std::vec![$($done),*]
+    };
+    ([$($done:expr),*] Do!($($block:tt)*) $($rest:tt)*) => {
+        $crate::__instructions!([$($done,)* $crate::Do!($($block)*)] $($rest)*)
+    };
+    ([$($done:expr),*] TagFrags!($($frags:tt)*) $($rest:tt)*) => {
+        $crate::__instructions!([$($done,)* $crate::TagFrags!($($frags)*)] $($rest)*)
+    };
+    ([$($done:expr),*] ($instr:expr) $($rest:tt)*) => {
+        $crate::__instructions!([$($done,)* $instr] $($rest)*)
+    };
+    ([$($done:expr),*] $name:ident($tag:expr) $($rest:tt)*) => {
+        $crate::__instructions!([$($done,)* $crate::Instruction::$name($tag)] $($rest)*)
+    };
+    ([$($done:expr),*] $name:ident $($rest:tt)*) => {
+        $crate::__instructions!([$($done,)* $crate::Instruction::$name] $($rest)*)
+    };
+}
 
-            Routine {
-                name: stringify!($name),
-                instrs: rule_instrs
-            }
-        }
+/// A named routine of a lexer.
+#[macro_export]
+macro_rules! routine {
+    (:$name:ident= $($instr:tt)*) => {
+        $crate::Routine::new(stringify!($name), $crate::__instructions!([] $($instr)*))
     };
 }
 
+/// A lexer made of `routine!`s, run in order.
+#[macro_export]
 macro_rules! lexer {
     ($($rule:expr)*) => {
-        Lexer {
-            rules: &vec![
-                $(
-                    $rule,
-                )*
-            ]
-        }
+        $crate::Lexer::new(::std::vec![
+            $(
+                $rule,
+            )*
+        ])
     };
 }
 
+/// A parser made of `rule!`s, tried in order, and optionally the
+/// `operators!` of its expressions.
+#[macro_export]
 macro_rules! parser {
-    ($($rule:expr)*) => {
-        Parser {
-            rules: &vec![
-                $(
-                    $rule,
-                )*
-            ]
-        }
+    ($($item:expr)*) => {
+        <$crate::Parser as ::std::iter::FromIterator<$crate::ParserItem>>::from_iter([
+            $(
+                $crate::ParserItem::from($item),
+            )*
+        ])
     };
 }
 
-macro_rules! rule {
-    ($($matches:expr)+ ; $($tags:expr)+) => {
-        Rule {
-            matches: vec![
-                $(
-                    $matches,
-                )+
-            ],
-            tags: vec![
-                $(
-                    $tags,
-                )+
-            ],
-            repeat: None,
-            add_all: false
-        }
+/// The `OperatorTable` of a `parser!`, written as in a grammar file:
+/// `operators!("expression", infixl(6, "+" "-") prefix(9, "-"))` calls
+/// `infixl(6, &["+", "-"])` and `prefix(9, &["-"])` on a table of
+/// "expression"s.
+#[macro_export]
+macro_rules! operators {
+    ($operand:expr, $($fixity:ident($precedence:expr, $($tag:literal)+))*) => {
+        $crate::OperatorTable::new($operand)$(.$fixity($precedence, &[$($tag),+]))*
     };
+}
 
-    ($($matches:expr)+ ;; $($tags:expr)+) => {
-        Rule {
-            matches: vec![
-                $(
-                    $matches,
-                )+
-            ],
-            tags: vec![
-                $(
-                    $tags,
-                )+
-            ],
-            repeat: None,
-            add_all: true
-        }
+/// A parser rule: `rule!("a" "b" ; "c")` combines an "a" and a "b" into a
+/// "c", a `;;` adds the tags to a single match instead, and a number between
+/// the semicolons, which may be a constant, repeats that match. A match can
+/// also be `"a" | "b"` for a token with either tag, `!"a"` for a token
+/// without it, `_` for any token other than the end of the input, a constant
+/// tag, or a `Match` in parentheses. Conditions on the tokens around a match are
+/// added with methods, e.g. `rule!("name" ;; "expression").not_followed_by("eq")`.
+#[macro_export]
+macro_rules! rule {
+    ($($rule:tt)+) => {
+        $crate::__rule!([] $($rule)+)
     };
+}
 
-    ($($matches:expr)+; $repeat:expr; $($tags:expr)+) => {
-        Rule {
-            matches: vec![
-                $(
-                    $matches,
-                )+
-            ],
-            tags: vec![
-                $(
-                    $tags,
-                )+
-            ],
-            repeat: Some($repeat),
-            add_all: false
-        }
+// Reads the matches of a `rule!` one at a time, up to the first `;`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __rule {
+    ([$($matches:expr),*] ;; $($tags:expr)+) => {
+        $crate::__rule!(@rule [$($matches),*] [.with_add_all()] $($tags)+)
     };
-
-    ($($matches:expr)+; $repeat:expr;; $($tags:expr)+) => {
-        Rule {
-            matches: vec![
-                $(
-                    $matches,
-                )+
-            ],
-            tags: vec![
+    ([$($matches:expr),*] ; $repeat:expr ;; $($tags:expr)+) => {
+        $crate::__rule!(@rule [$($matches),*] [.with_repeat($repeat).with_add_all()] $($tags)+)
+    };
+    ([$($matches:expr),*] ; $repeat:expr ; $($tags:expr)+) => {
+        $crate::__rule!(@rule [$($matches),*] [.with_repeat($repeat)] $($tags)+)
+    };
+    ([$($matches:expr),*] ; $($tags:expr)+) => {
+        $crate::__rule!(@rule [$($matches),*] [] $($tags)+)
+    };
+    ([$($matches:expr),*] _ $($rest:tt)+) => {
+        $crate::__rule!([$($matches,)* $crate::Match::Any] $($rest)+)
+    };
+    ([$($matches:expr),*] ! $tag:literal $($rest:tt)+) => {
+        $crate::__rule!([$($matches,)* $crate::Match::Not($tag)] $($rest)+)
+    };
+    ([$($matches:expr),*] $first:literal | $($rest:tt)+) => {
+        $crate::__rule!(@one_of [$($matches),*] [$first] $($rest)+)
+    };
+    ([$($matches:expr),*] $tag:literal $($rest:tt)+) => {
+        $crate::__rule!([$($matches,)* $crate::Match::Tag($tag)] $($rest)+)
+    };
+    ([$($matches:expr),*] ($m:expr) $($rest:tt)+) => {
+        $crate::__rule!([$($matches,)* $crate::Match::from($m)] $($rest)+)
+    };
+    ([$($matches:expr),*] $first:ident $(:: $more:ident)+ $($rest:tt)+) => {
+        $crate::__rule!([$($matches,)* $crate::Match::from($first $(:: $more)+)] $($rest)+)
+    };
+    ([$($matches:expr),*] $m:ident $($rest:tt)+) => {
+        $crate::__rule!([$($matches,)* $crate::Match::from($m)] $($rest)+)
+    };
+    (@one_of [$($matches:expr),*] [$($alternatives:literal),+] $tag:literal | $($rest:tt)+) => {
+        $crate::__rule!(@one_of [$($matches),*] [$($alternatives,)+ $tag] $($rest)+)
+    };
+    (@one_of [$($matches:expr),*] [$($alternatives:literal),+] $tag:literal $($rest:tt)+) => {
+        $crate::__rule!([$($matches,)* $crate::Match::OneOf(::std::vec![$($alternatives,)+ $tag])] $($rest)+)
+    };
+    (@rule [$($matches:expr),*] [$($with:tt)*] $($tags:expr)+) => {
+        $crate::Rule::new(
+            ::std::vec![$($matches),*],
+            ::std::vec![
                 $(
                     $tags,
                 )+
-            ],
-            repeat: Some($repeat),
-            add_all: true
-        }
+            ]
+        )$($with)*
     };
 }
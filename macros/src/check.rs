@@ -0,0 +1,114 @@
+use syn::{Error, LitStr};
+
+use crate::syntax::{Instr, Routine, Rule};
+
+/// Finds `Goto`s that cannot reach their label, and labels defined twice in
+/// one block. As in the lexer, a `Goto` can jump to a label of its own block
+/// or of a block around it.
+pub(crate) fn labels(routine: &Routine, errors: &mut Vec<Error>) {
+    fn walk(instrs: &[Instr], scopes: &mut Vec<Vec<String>>, errors: &mut Vec<Error>) {
+        let mut here = vec![];
+        for instr in instrs {
+            if let Instr::Tagged(name, label) = instr {
+                if name == "Label" {
+                    if here.contains(&label.value()) {
+                        errors.push(Error::new(label.span(), format!("label {:?} is defined twice in this block", label.value())));
+                    }
+                    here.push(label.value());
+                }
+            }
+        }
+        scopes.push(here);
+        for instr in instrs {
+            match instr {
+                Instr::Tagged(name, label) if name == "Goto" && !scopes.iter().any(|scope| scope.contains(&label.value())) => {
+                    errors.push(Error::new(
+                        label.span(),
+                        format!("Goto to label {:?}, which is not defined in this block or a block around it", label.value())
+                    ));
+                }
+                Instr::Block(block) => walk(block, scopes, errors),
+                _ => {}
+            }
+        }
+        scopes.pop();
+    }
+    walk(&routine.instrs, &mut vec![], errors);
+}
+
+/// The tags that the routines add.
+pub(crate) fn added_tags(routines: &[&Routine]) -> Vec<String> {
+    fn walk(instrs: &[Instr], tags: &mut Vec<String>) {
+        for instr in instrs {
+            match instr {
+                Instr::Tagged(name, tag) if name == "Add" => tags.push(tag.value()),
+                Instr::Block(block) => walk(block, tags),
+                _ => {}
+            }
+        }
+    }
+    let mut tags = vec![];
+    for routine in routines {
+        walk(&routine.instrs, &mut tags);
+    }
+    tags
+}
+
+/// The tags that `If`s in the routines test.
+pub(crate) fn tested_tags(routines: &[&Routine]) -> Vec<LitStr> {
+    fn walk(instrs: &[Instr], tags: &mut Vec<LitStr>) {
+        for instr in instrs {
+            match instr {
+                Instr::Tagged(name, tag) if name == "If" => tags.push(tag.clone()),
+                Instr::Block(block) => walk(block, tags),
+                _ => {}
+            }
+        }
+    }
+    let mut tags = vec![];
+    for routine in routines {
+        walk(&routine.instrs, &mut tags);
+    }
+    tags
+}
+
+pub(crate) fn rule_tags(rules: &[&Rule]) -> Vec<String> {
+    rules.iter().flat_map(|rule| rule.tags.iter().map(LitStr::value)).collect()
+}
+
+/// Reports the tags in `used` that no token can have: tags that are not
+/// `produced` and are not the text of a single character, which every token
+/// starts out tagged with, or "ws".
+pub(crate) fn produced(used: &[LitStr], produced: &[String], by: &str, errors: &mut Vec<Error>) {
+    for tag in used {
+        let value = tag.value();
+        if value.chars().count() == 1 || value == "ws" || produced.contains(&value) {
+            continue;
+        }
+        let mut message = format!("no token has the tag {:?}, it is never added by {}", value, by);
+        let closest = produced.iter()
+            .map(|candidate| (distance(&value, candidate), candidate))
+            .filter(|&(d, _)| d <= 2)
+            .min();
+        if let Some((_, candidate)) = closest {
+            message += &format!("; did you mean {:?}?", candidate);
+        }
+        errors.push(Error::new(tag.span(), message));
+    }
+}
+
+// The edit distance between two strings.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for j in 0..b.len() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == b[j] { previous } else { 1 + previous.min(row[j]).min(row[j + 1]) };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
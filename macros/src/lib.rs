@@ -0,0 +1,182 @@
+//! Procedural versions of the `lexer!` and `parser!` macros of rustuck, which
+//! take the same `routine!(...)` and `rule!(...)` syntax but check it while
+//! compiling:
+//!
+//! - unknown instructions and malformed rules are errors at the offending
+//!   tokens, instead of errors in the expanded code
+//! - every `Goto` must name a label in its block or a block around it, and a
+//!   label is defined at most once per block
+//! - `If`s in `lexer!` may only test tags that a routine adds, and rule
+//!   matches in `grammar!` only tags that a routine or a rule adds; the text
+//!   of a single character and "ws" are always allowed, as every token starts
+//!   out with them
+//!
+//! `parser!` and `grammar!` also take an `operators!(...)` item, written as
+//! in a grammar file, whose tags `grammar!` checks like those of rules.
+//!
+//! The expanded code names everything by its full path, so callers do not
+//! need to import `rustuck::Instruction::*`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::Error;
+
+mod check;
+mod syntax;
+
+use syntax::{Item, Items};
+
+/// Builds a `rustuck::Lexer` from `routine!(...)` items.
+#[proc_macro]
+pub fn lexer(input: TokenStream) -> TokenStream {
+    finish(expand(input.into(), Kind::Lexer))
+}
+
+/// Builds a `rustuck::Parser` from `rule!(...)` items, an optional
+/// `goal!("tag")` and optional `operators!(...)`, as in a grammar file. The
+/// tags the rules match are not checked, since the lexer
+/// is not known; `grammar!` checks them.
+#[proc_macro]
+pub fn parser(input: TokenStream) -> TokenStream {
+    finish(expand(input.into(), Kind::Parser))
+}
+
+/// Builds a `rustuck::Grammar` from the items of both `lexer!` and `parser!`,
+/// checking the tags the rules match against the tags the routines and rules
+/// add.
+#[proc_macro]
+pub fn grammar(input: TokenStream) -> TokenStream {
+    finish(expand(input.into(), Kind::Grammar))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Lexer,
+    Parser,
+    Grammar
+}
+
+fn finish(result: syn::Result<TokenStream2>) -> TokenStream {
+    result.unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn expand(input: TokenStream2, kind: Kind) -> syn::Result<TokenStream2> {
+    let Items(items) = syn::parse2(input)?;
+    let macro_name = match kind {
+        Kind::Lexer => "lexer",
+        Kind::Parser => "parser",
+        Kind::Grammar => "grammar"
+    };
+
+    let mut errors = vec![];
+    let (mut routines, mut rules, mut goal, mut operators) = (vec![], vec![], None, None);
+    for (name, item) in &items {
+        match item {
+            Item::Routine(routine) if kind != Kind::Parser => routines.push(routine),
+            Item::Rule(rule) if kind != Kind::Lexer => rules.push(rule),
+            Item::Goal(tag) if kind != Kind::Lexer => {
+                if goal.is_some() {
+                    errors.push(Error::new(name.span(), "the goal is already set"));
+                }
+                goal = Some(tag);
+            }
+            Item::Operators(table) if kind != Kind::Lexer => {
+                if operators.is_some() {
+                    errors.push(Error::new(name.span(), "the operators are already set"));
+                }
+                operators = Some(table);
+            }
+            _ => errors.push(Error::new(name.span(), format!("{name}! cannot be used in {macro_name}!")))
+        }
+    }
+
+    for routine in &routines {
+        check::labels(routine, &mut errors);
+    }
+    let added = check::added_tags(&routines);
+    check::produced(&check::tested_tags(&routines), &added, "any routine", &mut errors);
+    if kind == Kind::Grammar {
+        let mut added = added;
+        added.extend(check::rule_tags(&rules));
+        let mut matched: Vec<_> = rules.iter()
+            .flat_map(|rule| rule.matches.iter().flat_map(|m| m.tags()).chain(rule.context.iter().map(|(_, tag)| tag)).cloned())
+            .collect();
+        matched.extend(goal.cloned());
+        matched.extend(operators.iter().flat_map(|table| table.tags()).cloned());
+        check::produced(&matched, &added, "any routine or rule", &mut errors);
+    }
+
+    let mut errors = errors.into_iter();
+    if let Some(mut error) = errors.next() {
+        for other in errors {
+            error.combine(other);
+        }
+        return Err(error);
+    }
+
+    let lexer = syntax::lexer_tokens(&routines);
+    let parser = syntax::parser_tokens(&rules, goal, operators);
+    Ok(match kind {
+        Kind::Lexer => lexer,
+        Kind::Parser => parser,
+        Kind::Grammar => quote!(::rustuck::Grammar::new(&#lexer, &#parser))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(input: TokenStream2, kind: Kind) -> Vec<String> {
+        match expand(input, kind) {
+            Ok(_) => vec![],
+            Err(error) => error.into_iter().map(|e| e.to_string()).collect()
+        }
+    }
+
+    #[test]
+    fn grammar_checks() {
+        let lexer = quote! {
+            routine!(
+                :ints=
+                    TagFrags!("digit", "0" "1")
+                    If("digit") Skip Else Cancel
+                    Label("Loop")
+                    Next
+                    If("digti") Goto("Loop")
+                    Do!(Label("Inner") Goto("Outer") Wrap Back Add("int"))
+            )
+        };
+        assert_eq!(errors(lexer, Kind::Lexer), [
+            "Goto to label \"Outer\", which is not defined in this block or a block around it",
+            "no token has the tag \"digti\", it is never added by any routine; did you mean \"digit\"?"
+        ]);
+
+        let grammar = quote! {
+            routine!(:ints= TagFrags!("int", "0" "1") Label("A") Label("A"))
+            rule!("int" "+" "int" ; "sum")
+            rule!("sum" "\n" "whatever" ; "line")
+            rule!(_ "int" | "sum" !"sun" ;; "term")
+            goal!("lines")
+        };
+        assert_eq!(errors(grammar, Kind::Grammar), [
+            "label \"A\" is defined twice in this block",
+            "no token has the tag \"whatever\", it is never added by any routine or rule",
+            "no token has the tag \"sun\", it is never added by any routine or rule; did you mean \"sum\"?",
+            "no token has the tag \"lines\", it is never added by any routine or rule; did you mean \"line\"?"
+        ]);
+
+        assert_eq!(errors(quote!(rule!("a" "b" ; 2 ; "c")), Kind::Parser), ["cannot repeat match 2 of a rule with 2 matches"]);
+        assert_eq!(errors(quote!(routine!(:r= If("x") Repeat)), Kind::Lexer)[0],
+            "unknown instruction `Repeat`, expected one of Next, Else, Cancel, Skip, Back, Wrap, Delete, If, Add, Label, Goto, Do!, TagFrags!");
+        assert_eq!(errors(quote!(rule!("a" ; "b")), Kind::Lexer), ["rule! cannot be used in lexer!"]);
+        assert_eq!(errors(quote!(operators!("e", infix(1, "+"))), Kind::Parser),
+            ["unknown fixity `infix`, expected one of infixl, infixr, prefix, postfix"]);
+        assert_eq!(errors(quote!(operators!("e", infixl(1, "+")) operators!("e", prefix(2, "-"))), Kind::Parser),
+            ["the operators are already set"]);
+        assert_eq!(errors(quote!(rule!("int" ;; "e") operators!("e", infixl(1, "plus"))), Kind::Grammar),
+            ["no token has the tag \"int\", it is never added by any routine or rule",
+             "no token has the tag \"plus\", it is never added by any routine or rule"]);
+    }
+}
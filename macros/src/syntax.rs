@@ -0,0 +1,322 @@
+use proc_macro2::{Literal, TokenStream};
+use quote::{quote, quote_spanned};
+use syn::parse::{Parse, ParseStream};
+use syn::{parenthesized, Ident, LitInt, LitStr, Token};
+
+const UNITS: [&str; 7] = ["Next", "Else", "Cancel", "Skip", "Back", "Wrap", "Delete"];
+const WITH_TAG: [&str; 4] = ["If", "Add", "Label", "Goto"];
+const CONDITIONS: [&str; 4] = ["followed_by", "not_followed_by", "preceded_by", "not_preceded_by"];
+const FIXITIES: [&str; 4] = ["infixl", "infixr", "prefix", "postfix"];
+
+pub(crate) enum Instr {
+    Unit(Ident),
+    /// `If`, `Add`, `Label` or `Goto` and its string
+    Tagged(Ident, LitStr),
+    Block(Vec<Instr>)
+}
+
+pub(crate) struct Routine {
+    pub name: Ident,
+    pub instrs: Vec<Instr>
+}
+
+/// A match of a rule: `"a"`, `"a" | "b"`, `!"a"` or `_`
+pub(crate) enum Match {
+    Tag(LitStr),
+    OneOf(Vec<LitStr>),
+    Not(LitStr),
+    Any(Token![_])
+}
+
+pub(crate) struct Rule {
+    pub matches: Vec<Match>,
+    pub tags: Vec<LitStr>,
+    pub repeat: Option<usize>,
+    pub add_all: bool,
+    /// `.not_followed_by("tag")` and the like after the `rule!(...)`
+    pub context: Vec<(Ident, LitStr)>
+}
+
+/// `operators!("expression", infixl(6, "+" "-") prefix(9, "-"))`
+pub(crate) struct Operators {
+    pub operand: LitStr,
+    /// The fixity, precedence and tags of each group
+    pub groups: Vec<(Ident, LitInt, Vec<LitStr>)>
+}
+
+pub(crate) enum Item {
+    Routine(Routine),
+    Rule(Rule),
+    Goal(LitStr),
+    Operators(Operators)
+}
+
+/// The `routine!`, `rule!`, `goal!` and `operators!` items of a macro
+/// invocation, with the name each was written with.
+pub(crate) struct Items(pub Vec<(Ident, Item)>);
+
+impl Parse for Items {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut items = vec![];
+        while !input.is_empty() {
+            let kind: Ident = input.parse()?;
+            input.parse::<Token![!]>()?;
+            let content;
+            parenthesized!(content in input);
+            let item = match kind.to_string().as_str() {
+                "routine" => Item::Routine(content.parse()?),
+                "rule" => Item::Rule(content.parse()?),
+                "goal" => Item::Goal(content.parse()?),
+                "operators" => Item::Operators(content.parse()?),
+                other => return Err(syn::Error::new(kind.span(), format!("expected routine!, rule!, goal! or operators!, found {other}!")))
+            };
+            if !content.is_empty() {
+                return Err(content.error("unexpected tokens at the end of the item"));
+            }
+            let item = match item {
+                Item::Rule(mut rule) => {
+                    while input.peek(Token![.]) {
+                        input.parse::<Token![.]>()?;
+                        let method: Ident = input.parse()?;
+                        if !CONDITIONS.contains(&method.to_string().as_str()) {
+                            let expected = CONDITIONS.join(", ");
+                            return Err(syn::Error::new(method.span(), format!("unknown rule condition `{method}`, expected one of {expected}")));
+                        }
+                        let content;
+                        parenthesized!(content in input);
+                        rule.context.push((method, content.parse()?));
+                    }
+                    Item::Rule(rule)
+                }
+                item => item
+            };
+            items.push((kind, item));
+        }
+        Ok(Items(items))
+    }
+}
+
+impl Parse for Routine {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![:]>()?;
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        Ok(Routine { name, instrs: instructions(input)? })
+    }
+}
+
+fn instructions(input: ParseStream) -> syn::Result<Vec<Instr>> {
+    let mut instrs = vec![];
+    while !input.is_empty() {
+        let name: Ident = input.parse()?;
+        let text = name.to_string();
+        let instr = if UNITS.contains(&text.as_str()) {
+            Instr::Unit(name)
+        } else if WITH_TAG.contains(&text.as_str()) {
+            let content;
+            parenthesized!(content in input);
+            Instr::Tagged(name, content.parse()?)
+        } else if text == "Do" {
+            input.parse::<Token![!]>()?;
+            let content;
+            parenthesized!(content in input);
+            Instr::Block(instructions(&content)?)
+        } else if text == "TagFrags" {
+            input.parse::<Token![!]>()?;
+            let content;
+            parenthesized!(content in input);
+            let tag: LitStr = content.parse()?;
+            content.parse::<Token![,]>()?;
+            let mut block = vec![];
+            while !content.is_empty() {
+                let fragment: LitStr = content.parse()?;
+                block.push(Instr::Tagged(Ident::new("If", fragment.span()), fragment));
+                block.push(Instr::Tagged(Ident::new("Add", tag.span()), tag.clone()));
+            }
+            Instr::Block(block)
+        } else {
+            let expected = [&UNITS[..], &WITH_TAG[..], &["Do!", "TagFrags!"]].concat().join(", ");
+            return Err(syn::Error::new(name.span(), format!("unknown instruction `{text}`, expected one of {expected}")));
+        };
+        instrs.push(instr);
+    }
+    Ok(instrs)
+}
+
+impl Parse for Match {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Token![_]) {
+            return Ok(Match::Any(input.parse()?));
+        }
+        if input.peek(Token![!]) {
+            input.parse::<Token![!]>()?;
+            return Ok(Match::Not(input.parse()?));
+        }
+        let first = input.parse()?;
+        if !input.peek(Token![|]) {
+            return Ok(Match::Tag(first));
+        }
+        let mut alternatives = vec![first];
+        while input.peek(Token![|]) {
+            input.parse::<Token![|]>()?;
+            alternatives.push(input.parse()?);
+        }
+        Ok(Match::OneOf(alternatives))
+    }
+}
+
+impl Parse for Rule {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut matches = vec![];
+        while !input.peek(Token![;]) {
+            if input.is_empty() {
+                return Err(input.error("expected `;` after the tags the rule matches"));
+            }
+            matches.push(input.parse()?);
+        }
+        if matches.is_empty() {
+            return Err(input.error("a rule needs a tag to match"));
+        }
+        input.parse::<Token![;]>()?;
+
+        let mut repeat = None;
+        if input.peek(LitInt) {
+            let index: LitInt = input.parse()?;
+            let value: usize = index.base10_parse()?;
+            if value >= matches.len() {
+                return Err(syn::Error::new(
+                    index.span(),
+                    format!("cannot repeat match {value} of a rule with {} matches", matches.len())
+                ));
+            }
+            repeat = Some(value);
+            input.parse::<Token![;]>()?;
+        }
+        let add_all = input.peek(Token![;]);
+        if add_all {
+            input.parse::<Token![;]>()?;
+        }
+
+        let mut tags = vec![];
+        while !input.is_empty() {
+            tags.push(input.parse()?);
+        }
+        if tags.is_empty() {
+            return Err(input.error("a rule needs a tag to add"));
+        }
+        Ok(Rule { matches, tags, repeat, add_all, context: vec![] })
+    }
+}
+
+impl Parse for Operators {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let operand = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let mut groups = vec![];
+        while !input.is_empty() {
+            let fixity: Ident = input.parse()?;
+            if !FIXITIES.contains(&fixity.to_string().as_str()) {
+                let expected = FIXITIES.join(", ");
+                return Err(syn::Error::new(fixity.span(), format!("unknown fixity `{fixity}`, expected one of {expected}")));
+            }
+            let content;
+            parenthesized!(content in input);
+            let precedence: LitInt = content.parse()?;
+            precedence.base10_parse::<u32>()?;
+            content.parse::<Token![,]>()?;
+            let mut tags = vec![];
+            while !content.is_empty() {
+                tags.push(content.parse()?);
+            }
+            if tags.is_empty() {
+                return Err(content.error("expected the tags of the operators"));
+            }
+            groups.push((fixity, precedence, tags));
+        }
+        Ok(Operators { operand, groups })
+    }
+}
+
+impl Instr {
+    pub fn to_tokens(&self) -> TokenStream {
+        match self {
+            Instr::Unit(name) => quote_spanned!(name.span()=> ::rustuck::Instruction::#name),
+            Instr::Tagged(name, tag) => quote_spanned!(name.span()=> ::rustuck::Instruction::#name(#tag)),
+            Instr::Block(instrs) => {
+                let instrs = instrs.iter().map(Instr::to_tokens);
+                quote!(::rustuck::Instruction::Block(::std::vec![#(#instrs),*]))
+            }
+        }
+    }
+}
+
+impl Routine {
+    pub fn to_tokens(&self) -> TokenStream {
+        let name = LitStr::new(&self.name.to_string(), self.name.span());
+        let instrs = self.instrs.iter().map(Instr::to_tokens);
+        quote!(::rustuck::Routine::new(#name, ::std::vec![#(#instrs),*]))
+    }
+}
+
+impl Match {
+    /// Every tag the match names, with or without a `!`.
+    pub fn tags(&self) -> &[LitStr] {
+        match self {
+            Match::Tag(tag) | Match::Not(tag) => std::slice::from_ref(tag),
+            Match::OneOf(alternatives) => alternatives,
+            Match::Any(_) => &[]
+        }
+    }
+
+    pub fn to_tokens(&self) -> TokenStream {
+        match self {
+            Match::Tag(tag) => quote!(::rustuck::Match::Tag(#tag)),
+            Match::OneOf(alternatives) => quote!(::rustuck::Match::OneOf(::std::vec![#(#alternatives),*])),
+            Match::Not(tag) => quote!(::rustuck::Match::Not(#tag)),
+            Match::Any(underscore) => quote_spanned!(underscore.span=> ::rustuck::Match::Any)
+        }
+    }
+}
+
+impl Rule {
+    pub fn to_tokens(&self) -> TokenStream {
+        let matches = self.matches.iter().map(Match::to_tokens);
+        let tags = &self.tags;
+        let repeat = self.repeat.map(|index| {
+            let index = Literal::usize_unsuffixed(index);
+            quote!(.with_repeat(#index))
+        });
+        let add_all = self.add_all.then(|| quote!(.with_add_all()));
+        let context = self.context.iter().map(|(method, tag)| quote!(.#method(#tag)));
+        quote!(::rustuck::Rule::new(::std::vec![#(#matches),*], ::std::vec![#(#tags),*])#repeat #add_all #(#context)*)
+    }
+}
+
+pub(crate) fn lexer_tokens(routines: &[&Routine]) -> TokenStream {
+    let routines = routines.iter().map(|routine| routine.to_tokens());
+    quote!(::rustuck::Lexer::new(::std::vec![#(#routines),*]))
+}
+
+impl Operators {
+    /// Every tag the operators name, including the operand.
+    pub fn tags(&self) -> impl Iterator<Item = &LitStr> {
+        std::iter::once(&self.operand).chain(self.groups.iter().flat_map(|(_, _, tags)| tags))
+    }
+
+    pub fn to_tokens(&self) -> TokenStream {
+        let operand = &self.operand;
+        let groups = self.groups.iter().map(|(fixity, precedence, tags)| quote!(.#fixity(#precedence, &[#(#tags),*])));
+        quote!(::rustuck::OperatorTable::new(#operand)#(#groups)*)
+    }
+}
+
+pub(crate) fn parser_tokens(rules: &[&Rule], goal: Option<&LitStr>, operators: Option<&Operators>) -> TokenStream {
+    let rules = rules.iter().map(|rule| rule.to_tokens());
+    let goal = goal.map(|goal| quote!(.with_goal(#goal)));
+    let operators = operators.map(|operators| {
+        let table = operators.to_tokens();
+        quote!(.with_operators(#table))
+    });
+    quote!(::rustuck::Parser::new(::std::vec![#(#rules),*])#goal #operators)
+}
+
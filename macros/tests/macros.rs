@@ -0,0 +1,76 @@
+use rustuck::{lex_and_parse, Grammar, Lexer, Parser};
+use rustuck_macros::{grammar, lexer, parser};
+
+#[test]
+fn expanded_grammars_run() {
+    let lexer: Lexer = lexer!(
+        routine!(
+            :digits=
+                TagFrags!("digit", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+        )
+        routine!(
+            :ints=
+                If("digit") Skip Else Cancel
+                Label("Loop")
+                Next
+                If("digit") Goto("Loop")
+                Do!(Wrap Back Add("int"))
+        )
+        routine!(:noSpaces= If(" ") Delete)
+    );
+    let parser: Parser = parser!(
+        rule!("int" "+" "int" ; "sum")
+        rule!("sum" "\n" ; "line")
+        goal!("line")
+    );
+    let code = lex_and_parse(&lexer, &parser, "12 + 3\n4+").unwrap();
+    assert_eq!(code[0].content(), "12 + 3\n");
+    assert_eq!(parser.diagnose(&code)[0].text, "4+");
+
+    let grammar: Grammar = grammar!(
+        routine!(
+            :digits=
+                TagFrags!("int", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+        )
+        rule!("int" "+" "int" ; "sum")
+        rule!("sum" ; 0 ;; "expression")
+        rule!("int" ;; "operand").not_followed_by("+")
+        rule!("sum" | "operand" _ ;; "term")
+        goal!("expression")
+    );
+    let loaded = Grammar::parse(r#"
+        routine!(
+            :digits=
+                TagFrags!("int", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+        )
+        rule!("int" "+" "int" ; "sum")
+        rule!("sum" ; 0 ;; "expression")
+        rule!("int" ;; "operand").not_followed_by("+")
+        rule!("sum" | "operand" _ ;; "term")
+        goal!("expression")
+    "#).unwrap();
+    assert_eq!(format!("{:?}", grammar.parser()), format!("{:?}", loaded.parser()));
+    let code = lex_and_parse(grammar.lexer(), grammar.parser(), "1+2").unwrap();
+    assert!(code[0].tags.has("expression"));
+
+    let grammar: Grammar = grammar!(
+        routine!(
+            :digits=
+                TagFrags!("int", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+        )
+        rule!("int" ;; "expression")
+        operators!("expression", infixl(6, "+" "-") prefix(7, "-"))
+    );
+    let loaded = Grammar::parse(r#"
+        routine!(
+            :digits=
+                TagFrags!("int", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+        )
+        rule!("int" ;; "expression")
+        operators!("expression", infixl(6, "+" "-") prefix(7, "-"))
+    "#).unwrap();
+    assert_eq!(format!("{:?}", grammar.parser()), format!("{:?}", loaded.parser()));
+    let code = lex_and_parse(grammar.lexer(), grammar.parser(), "1-2+-3").unwrap();
+    assert_eq!(code[0].content(), "1-2+-3");
+    assert!(code[0].tags.has("expression"));
+}
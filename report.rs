@@ -0,0 +1,340 @@
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::ops::Range;
+
+use crate::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note"
+        })
+    }
+}
+
+/// Whether a report is rendered as plain text or with ANSI colors for a
+/// terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Style {
+    #[default]
+    Plain,
+    Ansi
+}
+
+/// A message attached to a span of the source. Primary labels are underlined
+/// with `^` and point at the problem, secondary ones with `-` and add context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub location: Range<usize>,
+    pub message: String,
+    pub primary: bool
+}
+
+/// A diagnostic message about a source text, rendered as snippets of the
+/// source with its labels underlined, like the errors of rustc.
+///
+/// Tabs are shown as four spaces. Other characters are one column wide, so
+/// labels after wide or combining characters are misaligned, unless the
+/// `unicode-width` feature is on, which measures them as a terminal shows
+/// them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report<'a> {
+    pub severity: Severity,
+    pub message: String,
+    pub file: &'a str,
+    pub source: &'a str,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>
+}
+
+impl<'a> Report<'a> {
+    pub fn new(severity: Severity, message: impl Into<String>, file: &'a str, source: &'a str) -> Self {
+        Report { severity, message: message.into(), file, source, labels: vec![], notes: vec![] }
+    }
+
+    /// A report with a primary label on a token.
+    pub fn at(severity: Severity, message: impl Into<String>, token: &ParseToken<'a>, label: impl Into<String>) -> Self {
+        Report::new(severity, message, token.file, token.body).with_label(token.location.clone(), label)
+    }
+
+    pub fn with_label(mut self, location: Range<usize>, message: impl Into<String>) -> Self {
+        self.labels.push(Label { location, message: message.into(), primary: true });
+        self
+    }
+
+    pub fn with_secondary(mut self, location: Range<usize>, message: impl Into<String>) -> Self {
+        self.labels.push(Label { location, message: message.into(), primary: false });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn render(&self, style: Style) -> String {
+        let index = LineIndex::new(self.source, PositionOptions::default());
+        let spans: Vec<Span> = self.labels.iter().map(|label| self.span(label, &index)).collect();
+        let slots: Vec<usize> = (0..spans.len()).filter(|&i| spans[i].start.0 != spans[i].end.0).collect();
+        let margin = slots.len() * 2;
+
+        let mut lines = BTreeSet::new();
+        for span in &spans {
+            lines.insert(span.start.0);
+            lines.insert(span.end.0);
+            if span.end.0 - span.start.0 <= 4 {
+                lines.extend(span.start.0..span.end.0);
+            }
+        }
+        let width = lines.last().map_or(1, |line| (line + 1).to_string().len());
+
+        let mut rows: Vec<Row> = vec![];
+        let mut previous: Option<usize> = None;
+        for &line in &lines {
+            if previous.is_some_and(|previous| line > previous + 1) {
+                rows.push(Row { number: None, gap: true, cells: vec![] });
+            }
+            previous = Some(line);
+
+            // the line itself, with the bars of the multi-line labels that
+            // continue through it
+            let mut cells = vec![];
+            for (slot, &i) in slots.iter().enumerate() {
+                if spans[i].start.0 < line && line <= spans[i].end.0 {
+                    put(&mut cells, slot * 2, "|", Paint::Label(self.labels[i].primary));
+                }
+            }
+            put(&mut cells, margin, &self.line_text(&index, line), Paint::Plain);
+            rows.push(Row { number: Some(line + 1), gap: false, cells });
+
+            let bars = |cells: &mut Vec<(char, Paint)>| {
+                for (slot, &i) in slots.iter().enumerate() {
+                    if spans[i].start.0 < line && line <= spans[i].end.0 {
+                        put(cells, slot * 2, "|", Paint::Label(self.labels[i].primary));
+                    }
+                }
+            };
+
+            // labels on this line only
+            let mut singles: Vec<usize> = (0..spans.len())
+                .filter(|&i| spans[i].start.0 == line && spans[i].end.0 == line)
+                .collect();
+            singles.sort_by_key(|&i| spans[i].start.1);
+            if let Some(&last) = singles.last() {
+                let mut cells = vec![];
+                bars(&mut cells);
+                for &i in &singles {
+                    let label = &self.labels[i];
+                    let marker = if label.primary { "^" } else { "-" };
+                    let length = spans[i].end.1 + spans[i].last - spans[i].start.1;
+                    put(&mut cells, margin + spans[i].start.1, &marker.repeat(length), Paint::Label(label.primary));
+                }
+                let end = singles.iter().map(|&i| spans[i].end.1 + spans[i].last).max().unwrap_or_default();
+                put(&mut cells, margin + end + 1, &self.labels[last].message, Paint::Label(self.labels[last].primary));
+                rows.push(Row { number: None, gap: false, cells });
+
+                let rest: Vec<usize> = singles[..singles.len() - 1].iter().copied()
+                    .filter(|&i| !self.labels[i].message.is_empty())
+                    .collect();
+                if !rest.is_empty() {
+                    let mut cells = vec![];
+                    bars(&mut cells);
+                    for &i in &rest {
+                        put(&mut cells, margin + spans[i].start.1, "|", Paint::Label(self.labels[i].primary));
+                    }
+                    rows.push(Row { number: None, gap: false, cells });
+                }
+                for (n, &i) in rest.iter().enumerate().rev() {
+                    let mut cells = vec![];
+                    bars(&mut cells);
+                    for &left in &rest[..n] {
+                        put(&mut cells, margin + spans[left].start.1, "|", Paint::Label(self.labels[left].primary));
+                    }
+                    put(&mut cells, margin + spans[i].start.1, &self.labels[i].message, Paint::Label(self.labels[i].primary));
+                    rows.push(Row { number: None, gap: false, cells });
+                }
+            }
+
+            // multi-line labels starting or ending on this line
+            for (slot, &i) in slots.iter().enumerate() {
+                let label = &self.labels[i];
+                let paint = Paint::Label(label.primary);
+                let marker = if label.primary { "^" } else { "-" };
+                let (column, bar, message) = if spans[i].start.0 == line {
+                    (spans[i].start.1, " ", "")
+                } else if spans[i].end.0 == line {
+                    (spans[i].end.1, "|", label.message.as_str())
+                } else {
+                    continue;
+                };
+                let mut cells = vec![];
+                bars(&mut cells);
+                put(&mut cells, slot * 2, bar, paint);
+                put(&mut cells, slot * 2 + 1, &"_".repeat(margin + column - slot * 2 - 1), paint);
+                put(&mut cells, margin + column, marker, paint);
+                put(&mut cells, margin + column + 2, message, paint);
+                rows.push(Row { number: None, gap: false, cells });
+            }
+        }
+
+        let mut out = format!(
+            "{}{}\n",
+            paint(style, &self.severity.to_string(), self.severity_color()),
+            paint(style, &format!(": {}", self.message), "")
+        );
+        let primary = self.labels.iter().find(|label| label.primary).or(self.labels.first());
+        let gutter = |text: &str| paint(style, text, "34");
+        if let Some(label) = primary {
+            let (line, column) = index.position(self.source, label.location.start);
+            out += &format!("{}{} {}:{}:{}\n", " ".repeat(width), gutter("-->"), self.file, line + 1, column + 1);
+            out += &format!("{}\n", gutter(&format!("{:width$} |", "")));
+        }
+        for row in &rows {
+            let number = match (row.number, row.gap) {
+                (_, true) => format!("{:width$}", "..."),
+                (Some(number), _) => format!("{:>width$} |", number),
+                (None, _) => format!("{:width$} |", "")
+            };
+            let mut line = gutter(&number);
+            if !row.cells.is_empty() {
+                line.push(' ');
+            }
+            let mut cells = &row.cells[..];
+            while let Some(&(_, first)) = cells.first() {
+                let run = cells.iter().take_while(|(_, p)| *p == first).count();
+                let text: String = cells[..run].iter().map(|(c, _)| c).collect();
+                line += &match first {
+                    Paint::Plain => text,
+                    Paint::Label(true) => paint(style, &text, self.severity_color()),
+                    Paint::Label(false) => paint(style, &text, "34")
+                };
+                cells = &cells[run..];
+            }
+            out += line.trim_end();
+            out.push('\n');
+        }
+        if !rows.is_empty() && !self.notes.is_empty() {
+            out += &format!("{}\n", gutter(&format!("{:width$} |", "")));
+        }
+        for note in &self.notes {
+            out += &format!("{} {}\n", gutter(&format!("{:width$} =", "")), paint(style, "note", "") + ": " + note);
+        }
+        out
+    }
+
+    fn severity_color(&self) -> &'static str {
+        match self.severity {
+            Severity::Error => "31",
+            Severity::Warning => "33",
+            Severity::Note => "36"
+        }
+    }
+
+    // The zero-based lines and columns, as shown, of the first and last
+    // characters of a label.
+    fn span(&self, label: &Label, index: &LineIndex) -> Span {
+        let Range { start, end } = label.location;
+        let mut last = end.max(start + 1) - 1;
+        while last > start && !self.source.is_char_boundary(last) {
+            last -= 1;
+        }
+        let last = last.max(start);
+        let shown = self.source.get(last..).and_then(|rest| rest.chars().next()).map_or(1, |c| char_width(c).max(1));
+        Span { start: self.column(index, start), end: self.column(index, last), last: shown }
+    }
+
+    fn column(&self, index: &LineIndex, offset: usize) -> (usize, usize) {
+        let (line, _) = index.position(self.source, offset);
+        let line_start = index.line_start(line).unwrap_or_default();
+        let offset = offset.clamp(line_start, self.source.len());
+        (line, shown_width(self.source.get(line_start..offset).unwrap_or_default()))
+    }
+
+    fn line_text(&self, index: &LineIndex, line: usize) -> String {
+        let start = index.line_start(line).unwrap_or(self.source.len());
+        let end = index.line_start(line + 1).unwrap_or(self.source.len());
+        self.source[start..end].trim_end_matches(['\n', '\r']).replace('\t', "    ")
+    }
+}
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(Style::Plain))
+    }
+}
+
+impl<'a> ParseDiagnostic<'a> {
+    /// A report of the run with a label on it and a note for every rule that
+    /// came close to matching it.
+    pub fn report(&self) -> Report<'a> {
+        let mut report = Report::new(Severity::Error, "could not parse these tokens", self.file, self.body)
+            .with_label(self.location.clone(), "not reduced to the goal");
+        for miss in &self.near_misses {
+            report = report.with_note(format!("closest: {}", miss));
+        }
+        report
+    }
+}
+
+struct Span {
+    start: (usize, usize),
+    end: (usize, usize),
+    // the width of the last character, at least one column
+    last: usize
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Paint {
+    Plain,
+    Label(bool)
+}
+
+struct Row {
+    number: Option<usize>,
+    gap: bool,
+    cells: Vec<(char, Paint)>
+}
+
+fn put(cells: &mut Vec<(char, Paint)>, at: usize, text: &str, paint: Paint) {
+    for (i, c) in text.chars().enumerate() {
+        while cells.len() <= at + i {
+            cells.push((' ', Paint::Plain));
+        }
+        cells[at + i] = (c, paint);
+    }
+}
+
+fn shown_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+#[cfg(feature = "unicode-width")]
+fn char_width(c: char) -> usize {
+    match c {
+        '\t' => 4,
+        c => unicode_width::UnicodeWidthChar::width(c).unwrap_or(0)
+    }
+}
+
+#[cfg(not(feature = "unicode-width"))]
+fn char_width(c: char) -> usize {
+    if c == '\t' { 4 } else { 1 }
+}
+
+// Bold text in an ANSI color, or in the default color if `color` is empty.
+fn paint(style: Style, text: &str, color: &str) -> String {
+    match style {
+        Style::Plain => text.to_string(),
+        Style::Ansi if color.is_empty() => format!("\x1b[1m{}\x1b[0m", text),
+        Style::Ansi => format!("\x1b[1;{}m{}\x1b[0m", color, text)
+    }
+}
@@ -0,0 +1,151 @@
+//! `serde` support, behind the `serde` feature.
+//!
+//! Tokens and parse trees serialize with their span as `start` and `end`
+//! offsets plus `line` and `char`, instead of the text they borrow, so they
+//! can only be serialized. Lexers, parsers, their parts and grammars go
+//! both ways: the names and tags they read are interned, as those of a
+//! loaded grammar are, so they do not borrow from the input.
+
+use serde::de::Deserializer;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// A list of tag names.
+impl Serialize for TagSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.names())
+    }
+}
+
+impl<'de> Deserialize<'de> for TagSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let names = Vec::<String>::deserialize(deserializer)?;
+        Ok(names.iter().map(String::as_str).collect())
+    }
+}
+
+impl Serialize for Token<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut token = serializer.serialize_struct("Token", 6)?;
+        token.serialize_field("start", &self.location.start)?;
+        token.serialize_field("end", &self.location.end)?;
+        token.serialize_field("line", &self.line)?;
+        token.serialize_field("char", &self.char)?;
+        token.serialize_field("file", self.file)?;
+        token.serialize_field("tags", &self.tags)?;
+        token.end()
+    }
+}
+
+impl Serialize for ParseToken<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut token = serializer.serialize_struct("ParseToken", 7)?;
+        token.serialize_field("start", &self.location.start)?;
+        token.serialize_field("end", &self.location.end)?;
+        token.serialize_field("line", &self.line)?;
+        token.serialize_field("char", &self.char)?;
+        token.serialize_field("file", self.file)?;
+        token.serialize_field("tags", &self.tags)?;
+        token.serialize_field("children", &self.children)?;
+        token.end()
+    }
+}
+
+/// The name and instructions, without the labels found from them.
+impl Serialize for Routine<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut routine = serializer.serialize_struct("Routine", 2)?;
+        routine.serialize_field("name", self.name)?;
+        routine.serialize_field("instrs", self.instrs())?;
+        routine.end()
+    }
+}
+
+// The strings read into a lexer or parser are interned, like the tags of a
+// loaded grammar, so they can be borrowed for any lifetime.
+pub(crate) fn interned<'de, 'a, D: Deserializer<'de>>(deserializer: D) -> Result<&'a str, D::Error> {
+    Ok(crate::tags::interned(&String::deserialize(deserializer)?))
+}
+
+pub(crate) fn interned_all<'de, 'a, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<&'a str>, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+    Ok(names.iter().map(|name| crate::tags::interned(name)).collect())
+}
+
+fn interned_option<'de, 'a, D: Deserializer<'de>>(deserializer: D) -> Result<Option<&'a str>, D::Error> {
+    let name = Option::<String>::deserialize(deserializer)?;
+    Ok(name.map(|name| crate::tags::interned(&name)))
+}
+
+/// Read as it is written, finding the labels again.
+impl<'de: 'a, 'a> Deserialize<'de> for Routine<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields<'a> {
+            #[serde(deserialize_with = "interned")]
+            name: &'a str,
+            #[serde(borrow)]
+            instrs: Vec<Instruction<'a>>
+        }
+        let Fields { name, instrs } = Fields::deserialize(deserializer)?;
+        Ok(Routine::new(name, instrs))
+    }
+}
+
+/// Read as it is written, interning the tags of the rules again.
+impl<'de: 'a, 'a> Deserialize<'de> for Parser<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields<'a> {
+            #[serde(borrow)]
+            rules: Vec<Rule<'a>>,
+            #[serde(deserialize_with = "interned_option", default)]
+            goal: Option<&'a str>,
+            #[serde(borrow, default)]
+            operators: Option<OperatorTable<'a>>
+        }
+        let Fields { rules, goal, operators } = Fields::deserialize(deserializer)?;
+        let mut parser = Parser::new(rules);
+        parser.goal = goal;
+        parser.operators = operators;
+        Ok(parser)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for OperatorTable<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Op<'a> {
+            #[serde(deserialize_with = "interned")]
+            tag: &'a str,
+            fixity: Fixity,
+            precedence: u32
+        }
+        #[derive(Deserialize)]
+        struct Fields<'a> {
+            #[serde(deserialize_with = "interned")]
+            operand: &'a str,
+            #[serde(borrow)]
+            operators: Vec<Op<'a>>
+        }
+        let Fields { operand, operators } = Fields::deserialize(deserializer)?;
+        Ok(operators.iter().fold(OperatorTable::new(operand), |table, op| table.with(op.fixity, op.precedence, &[op.tag])))
+    }
+}
+
+/// Read as a lexer and a parser, whose strings are interned.
+impl<'de> Deserialize<'de> for Grammar {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields<'a> {
+            #[serde(borrow)]
+            lexer: Lexer<'a>,
+            #[serde(borrow)]
+            parser: Parser<'a>
+        }
+        let Fields { lexer, parser } = Fields::deserialize(deserializer)?;
+        Ok(Grammar::new(&lexer, &parser))
+    }
+}
@@ -0,0 +1,141 @@
+use std::ops::Range;
+
+use crate::*;
+
+/// The id of a file registered in a `SourceMap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(u32);
+
+/// A position resolved by a `SourceMap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourcePosition {
+    pub file: FileId,
+    pub line: usize,
+    pub column: usize
+}
+
+struct SourceFile {
+    name: String,
+    span: Range<usize>,
+    lines: LineIndex
+}
+
+/// Many files lexed as one input, so that tokens from different files can be
+/// parsed together and still be told apart by their offsets.
+///
+/// The files are stored one after the other in a single text, each followed
+/// by a line break that belongs to no file, and tokens made from a file have
+/// offsets into that text. Any offset, including the end of a file, can be
+/// resolved back to its file, line and column.
+///
+/// Tokens borrow the map, so files found while parsing, e.g. by an `include`,
+/// are added after the tokens that named them are dropped.
+#[derive(Default)]
+pub struct SourceMap {
+    text: String,
+    files: Vec<SourceFile>,
+    options: PositionOptions
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    /// Sets how lines and columns are counted, also for the files already
+    /// registered.
+    pub fn with_options(mut self, options: PositionOptions) -> Self {
+        self.options = options;
+        for file in &mut self.files {
+            file.lines = LineIndex::new(&self.text[file.span.clone()], options);
+        }
+        self
+    }
+
+    /// Registers a file, returning its id.
+    pub fn add_file(&mut self, name: impl Into<String>, text: &str) -> FileId {
+        let start = self.text.len();
+        self.text.push_str(text);
+        self.files.push(SourceFile {
+            name: name.into(),
+            span: start..self.text.len(),
+            lines: LineIndex::new(text, self.options)
+        });
+        self.text.push('\n');
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    /// The id of the file last registered under a name.
+    pub fn file_id(&self, name: &str) -> Option<FileId> {
+        self.files.iter().rposition(|file| file.name == name).map(|i| FileId(i as u32))
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = FileId> {
+        (0..self.files.len() as u32).map(FileId)
+    }
+
+    pub fn name(&self, id: FileId) -> &str {
+        &self.files[id.0 as usize].name
+    }
+
+    /// The text of a file.
+    pub fn source(&self, id: FileId) -> &str {
+        &self.text[self.span(id)]
+    }
+
+    /// The offsets of a file in the whole text.
+    pub fn span(&self, id: FileId) -> Range<usize> {
+        self.files[id.0 as usize].span.clone()
+    }
+
+    /// The text of every file, which the offsets of tokens point into.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The file an offset is in, counting the end of a file as in it.
+    pub fn file_at(&self, offset: usize) -> Option<FileId> {
+        let i = self.files.partition_point(|file| file.span.start <= offset).checked_sub(1)?;
+        (offset <= self.files[i].span.end).then_some(FileId(i as u32))
+    }
+
+    /// The file, line and column of an offset.
+    pub fn resolve(&self, offset: usize) -> Option<SourcePosition> {
+        let id = self.file_at(offset)?;
+        let file = &self.files[id.0 as usize];
+        let (line, column) = file.lines.position(self.source(id), offset - file.span.start);
+        Some(SourcePosition { file: id, line, column })
+    }
+
+    /// The file a range is in and the range relative to that file, e.g. to
+    /// report it with `Report`.
+    pub fn localize(&self, range: Range<usize>) -> Option<(FileId, Range<usize>)> {
+        let id = self.file_at(range.start)?;
+        let span = self.span(id);
+        (range.end <= span.end).then(|| (id, range.start - span.start..range.end - span.start))
+    }
+
+    /// The tokens of a file, with offsets into the whole text, ready to be
+    /// lexed. The token marking the end of the file is at its end.
+    pub fn tokens(&self, id: FileId) -> Vec<Token<'_>> {
+        let file = &self.files[id.0 as usize];
+        let mut code = to_tokens_with(self.source(id), &file.name, self.options);
+        for token in &mut code {
+            token.location = token.location.start + file.span.start..token.location.end + file.span.start;
+            token.body = &self.text;
+        }
+        code
+    }
+
+    /// Lexes every file on its own, so that no token spans two files, and
+    /// returns their tokens in order.
+    pub fn lex_all(&self, lexer: &Lexer) -> Result<Vec<Token<'_>>, RustuckError> {
+        let mut tokens = vec![];
+        for id in self.files() {
+            let mut code = self.tokens(id);
+            lexer.lex(&mut code, &mut ())?;
+            tokens.append(&mut code);
+        }
+        Ok(tokens)
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+use smallvec::SmallVec;
+
+/// A tag name, interned in one table shared by every lexer, parser and
+/// input, so that tags are stored and compared as numbers. Ids are ordered
+/// by when their names were first interned.
+///
+/// Names are never removed from the table, so each distinct name is kept
+/// for as long as the program runs.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TagId(u32);
+
+#[derive(Default)]
+struct TagTable {
+    ids: HashMap<&'static str, TagId>,
+    names: Vec<&'static str>
+}
+
+fn table() -> &'static RwLock<TagTable> {
+    static TABLE: OnceLock<RwLock<TagTable>> = OnceLock::new();
+    TABLE.get_or_init(Default::default)
+}
+
+impl TagId {
+    /// The id of a tag name, adding it to the table if it is new.
+    pub fn intern(name: &str) -> TagId {
+        if let Some(tag) = TagId::get(name) {
+            return tag;
+        }
+        let mut table = table().write().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&tag) = table.ids.get(name) {
+            return tag;
+        }
+        let tag = TagId(u32::try_from(table.names.len()).expect("more than u32::MAX tag names"));
+        let name: &'static str = Box::leak(name.into());
+        table.names.push(name);
+        table.ids.insert(name, tag);
+        tag
+    }
+
+    /// The id of a tag name, if it was ever interned.
+    pub fn get(name: &str) -> Option<TagId> {
+        table().read().unwrap_or_else(PoisonError::into_inner).ids.get(name).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        table().read().unwrap_or_else(PoisonError::into_inner).names[self.0 as usize]
+    }
+}
+
+/// The copy of a name kept in the table, for names that must outlive the
+/// text they were read from, such as those of a loaded grammar.
+pub(crate) fn interned(name: &str) -> &'static str {
+    TagId::intern(name).name()
+}
+
+impl From<&str> for TagId {
+    fn from(name: &str) -> Self {
+        TagId::intern(name)
+    }
+}
+
+impl Display for TagId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Debug for TagId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.name())
+    }
+}
+
+/// The tags of a token, in the order they were added and without duplicates.
+/// Most tokens have a few, which are kept inline.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct TagSet {
+    ids: SmallVec<[TagId; 4]>
+}
+
+impl TagSet {
+    pub fn new() -> Self {
+        TagSet::default()
+    }
+
+    pub fn contains(&self, tag: TagId) -> bool {
+        self.ids.contains(&tag)
+    }
+
+    /// Whether the set contains the tag with the given name. This looks the
+    /// name up in the table, so code that checks a tag often should intern
+    /// it once and use `contains`.
+    pub fn has(&self, name: &str) -> bool {
+        TagId::get(name).is_some_and(|tag| self.contains(tag))
+    }
+
+    /// Adds a tag, returning whether it was not already in the set.
+    pub fn insert(&mut self, tag: impl Into<TagId>) -> bool {
+        let tag = tag.into();
+        if self.contains(tag) {
+            return false;
+        }
+        self.ids.push(tag);
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = TagId> + '_ {
+        self.ids.iter().copied()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.iter().map(TagId::name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+impl<T: Into<TagId>> FromIterator<T> for TagSet {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = TagSet::new();
+        for tag in iter {
+            set.insert(tag);
+        }
+        set
+    }
+}
+
+impl Debug for TagSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(&self.ids).finish()
+    }
+}
@@ -0,0 +1,61 @@
+// Generated by rustuck from a grammar file. Do not edit.
+
+#[allow(dead_code)]
+pub fn lexer() -> rustuck::Lexer<'static> {
+    use rustuck::Instruction::*;
+    rustuck::Lexer::new(vec![
+        rustuck::Routine::new("digits", vec![
+            Block(vec![
+                If("0"),
+                Add("digit"),
+                If("1"),
+                Add("digit"),
+                If("2"),
+                Add("digit"),
+                If("3"),
+                Add("digit"),
+                If("4"),
+                Add("digit"),
+                If("5"),
+                Add("digit"),
+                If("6"),
+                Add("digit"),
+                If("7"),
+                Add("digit"),
+                If("8"),
+                Add("digit"),
+                If("9"),
+                Add("digit")
+            ])
+        ]),
+        rustuck::Routine::new("ints", vec![
+            If("digit"),
+            Skip,
+            Else,
+            Cancel,
+            Label("Loop"),
+            Next,
+            If("digit"),
+            Goto("Loop"),
+            Block(vec![
+                Wrap,
+                Back,
+                Add("int")
+            ])
+        ]),
+        rustuck::Routine::new("noSpaces", vec![
+            If(" "),
+            Delete
+        ])
+    ])
+}
+
+#[allow(dead_code)]
+pub fn parser() -> rustuck::Parser<'static> {
+    use rustuck::Match::*;
+    rustuck::Parser::new(vec![
+        rustuck::Rule::new(vec![Tag("int"), Tag("+"), Tag("int")], vec!["sum"]),
+        rustuck::Rule::new(vec![Tag("sum"), Tag("\n")], vec!["line"]),
+        rustuck::Rule::new(vec![Tag("line")], vec!["lines"]).with_repeat(0).with_add_all()
+    ]).with_goal("line")
+}
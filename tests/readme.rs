@@ -0,0 +1,108 @@
+// Builds the grammar of the README from outside the crate, importing only
+// what the README does, and checks the other forms the macros accept.
+use rustuck::*;
+
+fn readme_lexer() -> Lexer<'static> {
+    lexer!(
+        routine!(
+            :digits=
+            TagFrags!("digit", "0" "1" "2" "3" "4" "5" "6" "7" "8" "9")
+            TagFrags!("nonzero", "1" "2" "3" "4" "5" "6" "7" "8" "9")
+        )
+        routine!(
+            :integers=
+            If("nonzero") Skip Else Cancel
+            Label("Digits") Next
+            If("digit") Goto("Digits")
+            Do!(Wrap Back Add("integer"))
+        )
+        routine!(
+            :zeroInt=
+            If("0") Add("integer")
+        )
+        routine!(
+            :keywords=
+            If("l") Skip Else Cancel Next
+            If("e") Skip Else Cancel Next
+            If("t") Skip Else Cancel Next
+            Do!(Wrap Back Add("let"))
+        )
+        routine!(
+            :names=
+            If("x") Add("name")
+        )
+        routine!(
+            :print=
+            If("p") Skip Else Cancel Next
+            If("r") Skip Else Cancel Next
+            If("i") Skip Else Cancel Next
+            If("n") Skip Else Cancel Next
+            If("t") Skip Else Cancel Next
+            Do!(Wrap Back Add("print"))
+        )
+        routine!(
+            :symbols=
+            If("=") Add("eq")
+            If("+") Add("operator")
+        )
+        routine!(
+            :noWs=
+            If("ws") Delete
+        )
+    )
+}
+
+fn readme_parser() -> Parser<'static> {
+    parser!(
+        rule!("integer" ;; "expression")
+        rule!("expression" "operator" "expression" ; "expression")
+        rule!("let" "name" "eq" "expression" ; "statement")
+        rule!("name" ;; "expression").not_followed_by("eq")
+        rule!("print" "expression" ; "statement")
+    )
+}
+
+#[test]
+fn readme_grammar() {
+    let (lexer, parser) = (readme_lexer(), readme_parser());
+    let code = "let x = 10 + 2\nprint x";
+    let parsed = lex_and_parse(&lexer, &parser, code).unwrap();
+
+    let statements: Vec<&str> = parsed.iter()
+        .filter(|token| token.tags.has("statement"))
+        .map(|token| token.content())
+        .collect();
+    assert_eq!(statements, ["let x = 10 + 2", "print x"]);
+}
+
+#[test]
+fn instructions_as_expressions() {
+    let add = Instruction::Add("x");
+    let lexer = lexer!(
+        routine!(
+            :exprs=
+            If("a") (add) Do!((rustuck::Instruction::Add("y")))
+        )
+    );
+    let code = &mut to_tokens("ab", "input");
+    lexer.lex(code, &mut ()).unwrap();
+    assert!(code[0].tags.has("x") && code[0].tags.has("y"));
+    assert!(!code[1].tags.has("x"));
+}
+
+#[test]
+fn operators_in_parser() {
+    let parser = parser!(
+        rule!("integer" ;; "expression")
+        operators!("expression", infixl(6, "operator") prefix(7, "operator"))
+    );
+    let loaded = Grammar::parse(r#"
+        rule!("integer" ;; "expression")
+        operators!("expression", infixl(6, "operator") prefix(7, "operator"))
+    "#).unwrap();
+    assert_eq!(format!("{:?}", parser), format!("{:?}", loaded.parser()));
+
+    let parsed = lex_and_parse(&readme_lexer(), &parser, "1 + 2 + 3").unwrap();
+    assert_eq!(parsed.len(), 1);
+    assert!(parsed[0].tags.has("expression"));
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use super::lexer::*;
+use super::token::*;
+use crate::{RustuckError, TagId};
+
+// One operation of a compiled routine. Jump targets are indices into the
+// routine's operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Next,
+    Back,
+    Add(TagId),
+    Wrap,
+    Delete,
+    Cancel,
+    // Continue if the current token has the tag, otherwise jump
+    Test(TagId, usize),
+    Jump(usize),
+    // Save the cursor and enter a block whose `Leave` is at the given index
+    Enter(usize),
+    // Restore the cursor saved by the innermost `Enter`
+    Leave,
+    // Leave this many blocks, keeping the cursor, and jump
+    Exit(usize, usize)
+}
+
+/// A routine compiled into a flat list of operations, with every label and
+/// condition resolved to a jump and every tag interned. It runs like the routine it was compiled
+/// from, without reporting to an observer.
+#[derive(Debug, Clone)]
+pub struct CompiledRoutine<'r> {
+    pub name: &'r str,
+    ops: Vec<Op>
+}
+
+/// A lexer whose routines have all been compiled.
+#[derive(Debug, Clone)]
+pub struct CompiledLexer<'a> {
+    pub routines: Vec<CompiledRoutine<'a>>
+}
+
+impl<'a> Lexer<'a> {
+    pub fn compile(&self) -> Result<CompiledLexer<'a>, RustuckError> {
+        let routines = self.rules.iter()
+            .map(Routine::compile)
+            .collect::<Result<_, _>>()?;
+        Ok(CompiledLexer { routines })
+    }
+}
+
+impl CompiledLexer<'_> {
+    pub fn lex(&self, code: &mut Vec<Token<'_>>) -> Result<(), RustuckError> {
+        for routine in &self.routines {
+            routine.start(code)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'r> Routine<'r> {
+    pub fn compile(&self) -> Result<CompiledRoutine<'r>, RustuckError> {
+        let mut compiler = Compiler {
+            routine: self.name,
+            ops: vec![],
+            starts: vec![],
+            fixups: vec![]
+        };
+        compiler.block(self.instrs(), &mut vec![])?;
+
+        for (pc, block, instr) in compiler.fixups {
+            let target = compiler.starts[block][instr];
+            compiler.ops[pc] = match compiler.ops[pc] {
+                Op::Test(tag, _) => Op::Test(tag, target),
+                Op::Jump(_) => Op::Jump(target),
+                Op::Exit(up, _) => Op::Exit(up, target),
+                op => op
+            };
+        }
+
+        Ok(CompiledRoutine { name: self.name, ops: compiler.ops })
+    }
+}
+
+struct Compiler<'r> {
+    routine: &'r str,
+    ops: Vec<Op>,
+    // For every block, the index of the first operation of each of its
+    // instructions, followed by the index of its end
+    starts: Vec<Vec<usize>>,
+    // Operations that jump to the start of an instruction: (operation, block,
+    // instruction)
+    fixups: Vec<(usize, usize, usize)>
+}
+
+impl<'r> Compiler<'r> {
+    fn jump(&mut self, op: Op, block: usize, instr: usize) {
+        self.fixups.push((self.ops.len(), block, instr));
+        self.ops.push(op);
+    }
+
+    // `scopes` holds the id and labels of every enclosing block.
+    fn block(
+        &mut self,
+        instrs: &[Instruction<'r>],
+        scopes: &mut Vec<(usize, HashMap<&'r str, usize>)>
+    ) -> Result<(), RustuckError> {
+        let id = self.starts.len();
+        self.starts.push(vec![]);
+        let mut labels = HashMap::new();
+        for (i, instr) in instrs.iter().enumerate() {
+            if let Instruction::Label(label) = instr {
+                labels.entry(*label).or_insert(i);
+            }
+        }
+        scopes.push((id, labels));
+
+        let end = instrs.len();
+        for (i, instr) in instrs.iter().enumerate() {
+            self.starts[id].push(self.ops.len());
+            match instr {
+                Instruction::Next => self.ops.push(Op::Next),
+                Instruction::Back => self.ops.push(Op::Back),
+                Instruction::Wrap => self.ops.push(Op::Wrap),
+                Instruction::Delete => self.ops.push(Op::Delete),
+                Instruction::Cancel => self.ops.push(Op::Cancel),
+                Instruction::Skip | Instruction::Label(_) => {}
+                Instruction::Add(tag) => self.ops.push(Op::Add(TagId::intern(tag))),
+                Instruction::If(tag) => {
+                    let tag = TagId::intern(tag);
+                    let skip = if instrs.get(i + 2) == Some(&Instruction::Else) { 3 } else { 2 };
+                    self.jump(Op::Test(tag, 0), id, (i + skip).min(end));
+                }
+                Instruction::Else => self.jump(Op::Jump(0), id, (i + 2).min(end)),
+                Instruction::Goto(label) => {
+                    let Some((up, (block, target))) = scopes.iter().rev()
+                        .enumerate()
+                        .find_map(|(up, (block, labels))| Some((up, (*block, *labels.get(label)?))))
+                    else {
+                        return Err(RustuckError::UndefinedLabel {
+                            routine: self.routine.to_string(),
+                            label: label.to_string()
+                        });
+                    };
+                    let op = if up == 0 { Op::Jump(0) } else { Op::Exit(up, 0) };
+                    self.jump(op, block, target + 1);
+                }
+                Instruction::Block(inside) => {
+                    let enter = self.ops.len();
+                    self.ops.push(Op::Enter(0));
+                    self.block(inside, scopes)?;
+                    self.ops[enter] = Op::Enter(self.ops.len());
+                    self.ops.push(Op::Leave);
+                }
+            }
+        }
+        self.starts[id].push(self.ops.len());
+
+        scopes.pop();
+        Ok(())
+    }
+}
+
+// The cursor saved when entering a block.
+struct Frame {
+    index: usize,
+    start_index: usize,
+    leave: usize
+}
+
+impl CompiledRoutine<'_> {
+    pub fn start(&self, code: &mut Vec<Token<'_>>) -> Result<(), RustuckError> {
+        let mut frames: Vec<Frame> = vec![];
+        let mut start_index: usize = 0;
+
+        while !self.ops.is_empty() && start_index < code.len() {
+            let mut index = start_index;
+            let mut pc = 0;
+            frames.clear();
+
+            while pc < self.ops.len() {
+                match self.ops[pc] {
+                    Op::Next => {
+                        index += 1;
+                        pc += 1;
+                    }
+                    Op::Back => {
+                        index = index.saturating_sub(1);
+                        pc += 1;
+                    }
+                    Op::Add(tag) => {
+                        code[index].tags.insert(tag);
+                        pc += 1;
+                    }
+                    Op::Wrap => {
+                        if start_index >= index {
+                            return Err(RustuckError::WrapUnderflow {
+                                routine: self.name.to_string(),
+                                start_index,
+                                index
+                            });
+                        }
+                        wrap_tokens(code, start_index, index);
+                        index = start_index + 1;
+                        pc += 1;
+                    }
+                    Op::Delete => {
+                        code.remove(index);
+                        if index == start_index {
+                            start_index = start_index.wrapping_sub(1);
+                        }
+                        index = index.wrapping_sub(1);
+                        pc += 1;
+                    }
+                    Op::Cancel => match frames.pop() {
+                        Some(frame) => {
+                            index = frame.index;
+                            start_index = frame.start_index;
+                            pc = frame.leave + 1;
+                        }
+                        None => break
+                    }
+                    Op::Test(tag, target) => {
+                        if code[index].tags.contains(tag) {
+                            pc += 1;
+                        } else {
+                            pc = target;
+                        }
+                    }
+                    Op::Jump(target) => pc = target,
+                    Op::Enter(leave) => {
+                        frames.push(Frame { index, start_index, leave });
+                        pc += 1;
+                    }
+                    Op::Leave => {
+                        let frame = frames.pop().expect("Leave without Enter");
+                        index = frame.index;
+                        start_index = frame.start_index;
+                        pc += 1;
+                    }
+                    Op::Exit(up, target) => {
+                        frames.truncate(frames.len() - up);
+                        pc = target;
+                    }
+                }
+
+                // running off the tokens ends the innermost block, or the cycle
+                while index >= code.len() {
+                    let Some(frame) = frames.pop() else {
+                        pc = self.ops.len();
+                        break;
+                    };
+                    index = frame.index;
+                    start_index = frame.start_index;
+                    pc = frame.leave + 1;
+                }
+            }
+
+            start_index = start_index.wrapping_add(1);
+        }
+        Ok(())
+    }
+}
@@ -2,102 +2,187 @@
 
 use std::{collections::HashMap};
 
+use super::observer::*;
 use super::token::*;
+use crate::{RustuckError, TagId, TagSet};
 
+/// A list of routines run one after the other. The lexer owns its routines,
+/// so one built from string literals is a `Lexer<'static>` that can be kept
+/// in a `static`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Lexer<'a> {
-    pub rules: &'a Vec<Routine<'a>>,
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub rules: Vec<Routine<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(rules: Vec<Routine<'a>>) -> Self {
+        Lexer { rules }
+    }
 }
 
 impl Lexer<'_> {
-    pub fn lex<'a>(&'a self, code: &mut Vec<Token<'a>>, verbose: bool) {
-        for rule in self.rules {
-            if verbose {
-                println!("Starting next rule!");
-            }
-            rule.start(code, verbose);
+    pub fn lex(&self, code: &mut Vec<Token<'_>>, observer: &mut dyn LexObserver) -> Result<(), RustuckError> {
+        for rule in &self.rules {
+            observer.observe(&LexEvent::RoutineStarted { routine: rule.name });
+            rule.start(code, observer)?;
         }
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+/// A named list of instructions that is run once starting on every token.
+/// 
+/// Labels are resolved when the routine is created, so a `Goto` may jump
+/// forward as well as backward. A `Goto` can reach the labels of its own 
+/// block and of every block enclosing it, innermost first, but not the labels
+/// inside a nested block. Jumping to an enclosing label leaves the blocks in 
+/// between, keeping the cursor where it was, like a `break` or `continue`.
+#[derive(Debug, Clone)]
 pub struct Routine<'r> {
     pub name: &'r str,
-    pub instrs: Vec<Instruction<'r>>
+    // private, as the labels and tags are found from them once
+    instrs: Vec<Instruction<'r>>,
+    labels: LabelScope<'r>
+}
+
+impl<'r> Routine<'r> {
+    pub fn new(name: &'r str, instrs: Vec<Instruction<'r>>) -> Self {
+        let labels = LabelScope::new(&instrs);
+        Routine { name, instrs, labels }
+    }
+
+    pub fn instrs(&self) -> &[Instruction<'r>] {
+        &self.instrs
+    }
 }
 
 impl Routine<'_> {
-    pub fn start<'a>(&'a self, code: &mut Vec<Token<'a>>, verbose: bool) {
+    pub fn start(&self, code: &mut Vec<Token<'_>>, observer: &mut dyn LexObserver) -> Result<(), RustuckError> {
         let mut machine : Ltm = Ltm {
+            routine: self.name,
             index: 0,
             start_index: 0,
             rule_index: 0,
-            keep_going: true
+            keep_going: !self.instrs.is_empty() && !code.is_empty()
         };
-        let map: &mut HashMap<&str, usize> = &mut HashMap::new();
+        let scopes = &mut vec![&self.labels];
         while machine.keep_going { 
-            machine.cycle(code, &self.instrs, map, verbose);
+            machine.cycle(code, &self.instrs, scopes, observer)?;
         }
+        Ok(())
     }
 }
 
-struct Ltm { // Lexer Turing Machine
+/// The labels of one block of instructions, by name, the ids of the tags of
+/// its `Add` and `If` instructions, by index, and the scopes of the blocks
+/// nested in it, by the index of their `Block` instruction.
+#[derive(Debug, Clone, Default)]
+struct LabelScope<'r> {
+    labels: HashMap<&'r str, usize>,
+    tags: HashMap<usize, TagId>,
+    blocks: HashMap<usize, LabelScope<'r>>
+}
+
+impl<'r> LabelScope<'r> {
+    fn new(instrs: &[Instruction<'r>]) -> Self {
+        let mut scope = LabelScope::default();
+        for (i, instr) in instrs.iter().enumerate() {
+            match instr {
+                Instruction::Label(label) => {
+                    scope.labels.entry(label).or_insert(i);
+                }
+                Instruction::Add(tag) | Instruction::If(tag) => {
+                    scope.tags.insert(i, TagId::intern(tag));
+                }
+                Instruction::Block(inside) => {
+                    scope.blocks.insert(i, LabelScope::new(inside));
+                }
+                _ => {}
+            }
+        }
+        scope
+    }
+}
+
+// How execution continues after a step.
+enum Flow {
+    Continue,
+    // Jump to the instruction at `target` in the block `up` levels out
+    Jump { up: usize, target: usize }
+}
+
+struct Ltm<'r> { // Lexer Turing Machine
+    routine: &'r str,
     index: usize,
     start_index: usize,
     rule_index: usize,
     keep_going: bool
 }
 
-impl Ltm {
-    // returns: whether or not to continue
-    fn step<'a>(&mut self, code: &mut Vec<Token<'a>>, instrs: &Vec<Instruction<'a>>, labels: &mut HashMap<&'a str, usize>, verbose: bool) {
+impl Ltm<'_> {
+    fn step<'r>(&mut self, code: &mut Vec<Token<'_>>, instrs: &Vec<Instruction<'r>>, scopes: &mut Vec<&LabelScope<'r>>, observer: &mut dyn LexObserver) -> Result<Flow, RustuckError> {
         let instr = &instrs[self.rule_index];
-        let tok = &mut code[self.index];
+        let len = code.len();
+        let Some(tok) = code.get_mut(self.index) else {
+            return Err(RustuckError::CursorOutOfBounds {
+                routine: self.routine.to_string(),
+                index: self.index,
+                len
+            });
+        };
+        observer.observe(&LexEvent::InstructionExecuted { instruction: instr, index: self.index });
 
         match instr {
             Instruction::Next => {
                 self.rule_index += 1;
                 self.index += 1;
-                if verbose {
-                    println!("Switching to the next token ({}) and the next rule.", code[self.index]);
-                }
+                observer.observe(&LexEvent::CursorMoved { index: self.index, token: code.get(self.index) });
             }
             Instruction::Skip => {
                 self.rule_index += 1;
-                if verbose {
-                    println!("Skipping to the next rule.");
-                }
             }
             Instruction::Back => {
                 self.index = self.index.saturating_sub(1);
                 self.rule_index += 1;
-                if verbose {
-                    println!("Going back to the last token ({}).", code[self.index])
-                }
+                observer.observe(&LexEvent::CursorMoved { index: self.index, token: code.get(self.index) });
             }
             Instruction::Add(tag) => {
-                tok.tags.push(tag);
+                tok.tags.insert(scopes[scopes.len() - 1].tags[&self.rule_index]);
                 self.rule_index += 1;
-                if verbose {
-                    println!("Adding the tag \"{0}\" to the token {1}.", tag, code[self.index]);
-                }
+                observer.observe(&LexEvent::TagAdded { tag, token: &code[self.index] });
             }
             Instruction::Block(inside) => {
                 let mut machine : Ltm = Ltm {
+                    routine: self.routine,
                     index: self.index,
                     start_index: self.start_index,
                     rule_index: 0,
-                    keep_going: true
+                    keep_going: !inside.is_empty() && self.index < code.len()
                 };
-                let map: &mut HashMap<&str, usize> = &mut HashMap::new();
-                while machine.keep_going { 
-                    machine.step(code, inside, map, verbose);
+                let scope = &scopes[scopes.len() - 1].blocks[&self.rule_index];
+                scopes.push(scope);
+                let mut flow = Flow::Continue;
+                while machine.keep_going && matches!(flow, Flow::Continue) { 
+                    flow = machine.step(code, inside, scopes, observer)?;
+                }
+                scopes.pop();
+
+                match flow {
+                    Flow::Continue => self.rule_index += 1,
+                    Flow::Jump { up, target } => {
+                        self.index = machine.index;
+                        self.start_index = machine.start_index;
+                        if up > 1 {
+                            return Ok(Flow::Jump { up: up - 1, target });
+                        }
+                        self.rule_index = target + 1;
+                    }
                 }
-                self.rule_index += 1;
             }
             Instruction::Delete => {
-                if verbose {
-                    println!("Deleting the token {}", code[self.index]);
-                }
+                observer.observe(&LexEvent::TokenDeleted { token: &code[self.index] });
                 code.remove(self.index);
                 if self.index == self.start_index {
                     self.start_index = self.start_index.wrapping_sub(1);
@@ -106,99 +191,112 @@ impl Ltm {
                 self.rule_index += 1;
             }
             Instruction::Wrap => {
-                let loc_range = code[self.start_index].location.start..code[self.index - 1].location.end;
-                for _ in (self.start_index + 1)..self.index {
-                    code.remove(self.start_index + 1);
+                if self.start_index >= self.index {
+                    return Err(RustuckError::WrapUnderflow {
+                        routine: self.routine.to_string(),
+                        start_index: self.start_index,
+                        index: self.index
+                    });
                 }
-
-                code[self.start_index] = Token {
-                    location: loc_range,
-                    body: code[0].body,
-                    tags: vec![],
-                    ..code[self.start_index]
-                };
+                let wrapped = self.index - self.start_index;
+                wrap_tokens(code, self.start_index, self.index);
                 self.index = self.start_index + 1;
                 self.rule_index += 1;
 
-                if verbose {
-                    println!("Wrapping all the previous tokens into {}.", code[self.start_index].content());
-                }
+                observer.observe(&LexEvent::TokensWrapped { 
+                    token: &code[self.start_index], 
+                    count: wrapped 
+                });
             }
             Instruction::If(cond) => {
-                if code[self.index].tags.contains(cond) {
+                let satisfied = code[self.index].tags.contains(scopes[scopes.len() - 1].tags[&self.rule_index]);
+                observer.observe(&LexEvent::ConditionChecked { tag: cond, token: &code[self.index], satisfied });
+                if satisfied {
                     self.rule_index += 1;
-                    if verbose {
-                        println!("Condition satisfied (tag {0} found on token {1}).", cond, code[self.index]);
-                    }
                 }
                 else if instrs.get(self.rule_index + 2) == Some(&Instruction::Else) {
                     self.rule_index += 3;
-                    if verbose {
-                        println!("Condition NOT satisfied (tag {0} not found on token {1}), moving to Else clause", 
-                        cond, code[self.index]);
-                    }
                 }
                 else {
                     self.rule_index += 2;
-                    if verbose {
-                        println!("Condition NOT satisfied (tag {0} not found on token {1}).", cond, code[self.index]);
-                    }
                 }
             }
             Instruction::Cancel => {
                 self.keep_going = false;
-                if verbose {
-                    println!("Cancelling cycle.");
-                }
-                return;
+                observer.observe(&LexEvent::Cancelled);
+                return Ok(Flow::Continue);
             }
             Instruction::Else => self.rule_index += 2,
             Instruction::Label(label) => {
-                labels.insert(label, self.rule_index);
                 self.rule_index += 1;
-                if verbose {
-                    println!("Label created: {}.", label);
-                }
+                observer.observe(&LexEvent::LabelReached { label });
             }
             Instruction::Goto(label) => {
-                self.rule_index = labels[label] + 1;
-                if verbose {
-                    println!("Went to label {}.", label);
+                let Some((up, target)) = scopes.iter().rev()
+                    .enumerate()
+                    .find_map(|(up, scope)| Some((up, *scope.labels.get(label)?))) 
+                else {
+                    return Err(RustuckError::UndefinedLabel {
+                        routine: self.routine.to_string(),
+                        label: label.to_string()
+                    });
+                };
+                observer.observe(&LexEvent::Jumped { label });
+                if up > 0 {
+                    return Ok(Flow::Jump { up, target });
                 }
+                self.rule_index = target + 1;
             }
             // _ => panic!("Unknown instruction type called! ({:?})", instr)
         }
 
         self.keep_going = self.rule_index < instrs.len() && self.index < code.len();
+        Ok(Flow::Continue)
     }
 
-    fn cycle<'a>(&mut self, code: &mut Vec<Token<'a>>, instrs: &Vec<Instruction<'a>>, labels: &mut HashMap<&'a str, usize>, verbose: bool) {
+    fn cycle<'r>(&mut self, code: &mut Vec<Token<'_>>, instrs: &Vec<Instruction<'r>>, scopes: &mut Vec<&LabelScope<'r>>, observer: &mut dyn LexObserver) -> Result<(), RustuckError> {
+        observer.observe(&LexEvent::CycleStarted { routine: self.routine, start_index: self.start_index });
         while self.keep_going {
-            self.step(code, instrs, labels, verbose);
-        }
-        
-        if verbose {
-            println!();
+            // the routine's own block is the outermost scope, so every jump
+            // out of it has been resolved by now
+            self.step(code, instrs, scopes, observer)?;
         }
+        observer.observe(&LexEvent::CycleEnded { routine: self.routine });
+
         self.rule_index = 0;
         self.start_index = self.start_index.wrapping_add(1);
         self.index = self.start_index;
         self.keep_going = self.rule_index < instrs.len() && self.index < code.len();
+        Ok(())
     }
 }
 
-#[derive(Debug, PartialEq)]
+// Merges the tokens in `start..end` into one untagged token.
+pub(crate) fn wrap_tokens(code: &mut Vec<Token<'_>>, start: usize, end: usize) {
+    let loc_range = code[start].location.start..code[end - 1].location.end;
+    code.drain(start + 1..end);
+
+    code[start] = Token {
+        location: loc_range,
+        body: code[0].body,
+        tags: TagSet::new(),
+        ..code[start]
+    };
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction<'a> {
-    Block(Vec<Instruction<'a>>),
+    Block(#[cfg_attr(feature = "serde", serde(borrow))] Vec<Instruction<'a>>),
     Next,
-    If(&'a str),
+    If(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned"))] &'a str),
     Else,
     Cancel,
     Skip,
     Back,
     Wrap,
     Delete,
-    Add(&'a str),
-    Label(&'a str),
-    Goto(&'a str)
+    Add(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned"))] &'a str),
+    Label(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned"))] &'a str),
+    Goto(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned"))] &'a str)
 }
\ No newline at end of file
@@ -1,2 +1,7 @@
+pub mod bytecode;
 pub mod lexer;
-pub mod token;
\ No newline at end of file
+pub mod observer;
+pub mod position;
+pub mod stream;
+pub mod token;
+pub mod validate;
\ No newline at end of file
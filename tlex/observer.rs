@@ -0,0 +1,59 @@
+use super::lexer::Instruction;
+use super::token::Token;
+
+/// Something that happened while a lexer was running.
+#[derive(Debug)]
+pub enum LexEvent<'e> {
+    RoutineStarted { routine: &'e str },
+    CycleStarted { routine: &'e str, start_index: usize },
+    CycleEnded { routine: &'e str },
+    /// Emitted before an instruction runs, with the cursor it runs on
+    InstructionExecuted { instruction: &'e Instruction<'e>, index: usize },
+    /// The cursor moved, either forward (`Next`) or backward (`Back`)
+    CursorMoved { index: usize, token: Option<&'e Token<'e>> },
+    ConditionChecked { tag: &'e str, token: &'e Token<'e>, satisfied: bool },
+    TagAdded { tag: &'e str, token: &'e Token<'e> },
+    /// `count` tokens were merged into `token`
+    TokensWrapped { token: &'e Token<'e>, count: usize },
+    TokenDeleted { token: &'e Token<'e> },
+    LabelReached { label: &'e str },
+    Jumped { label: &'e str },
+    Cancelled
+}
+
+/// Receives the events of a running lexer, e.g. to log them, collect metrics
+/// or step through a routine.
+pub trait LexObserver {
+    fn observe(&mut self, event: &LexEvent<'_>);
+}
+
+/// Ignores every event.
+impl LexObserver for () {
+    fn observe(&mut self, _event: &LexEvent<'_>) {}
+}
+
+/// Prints every lexer and parser event to stdout.
+#[derive(Debug, Default)]
+pub struct PrintObserver;
+
+impl LexObserver for PrintObserver {
+    fn observe(&mut self, event: &LexEvent<'_>) {
+        match event {
+            LexEvent::RoutineStarted { routine } => println!("Starting routine {}!", routine),
+            LexEvent::CycleStarted { .. } | LexEvent::InstructionExecuted { .. } => {}
+            LexEvent::CycleEnded { .. } => println!(),
+            LexEvent::CursorMoved { token: Some(token), .. } => println!("Moving to the token {}.", token),
+            LexEvent::CursorMoved { token: None, .. } => println!("Moving past the last token."),
+            LexEvent::ConditionChecked { tag, token, satisfied: true } =>
+                println!("Condition satisfied (tag {0} found on token {1}).", tag, token),
+            LexEvent::ConditionChecked { tag, token, satisfied: false } =>
+                println!("Condition NOT satisfied (tag {0} not found on token {1}).", tag, token),
+            LexEvent::TagAdded { tag, token } => println!("Adding the tag \"{0}\" to the token {1}.", tag, token),
+            LexEvent::TokensWrapped { token, .. } => println!("Wrapping all the previous tokens into {}.", token.content()),
+            LexEvent::TokenDeleted { token } => println!("Deleting the token {}", token),
+            LexEvent::LabelReached { label } => println!("Reached label {}.", label),
+            LexEvent::Jumped { label } => println!("Went to label {}.", label),
+            LexEvent::Cancelled => println!("Cancelling cycle.")
+        }
+    }
+}
@@ -0,0 +1,129 @@
+/// The unit that columns are counted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColumnUnit {
+    /// UTF-8 bytes from the start of the line
+    Bytes,
+    /// Unicode scalar values from the start of the line
+    #[default]
+    Chars,
+    /// UTF-16 code units from the start of the line, as used by LSP clients
+    Utf16
+}
+
+impl ColumnUnit {
+    /// The width of a piece of text in this unit.
+    pub fn width(self, text: &str) -> usize {
+        match self {
+            ColumnUnit::Bytes => text.len(),
+            ColumnUnit::Chars => text.chars().count(),
+            ColumnUnit::Utf16 => text.chars().map(char::len_utf16).sum()
+        }
+    }
+}
+
+/// How lines and columns are reported on tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PositionOptions {
+    pub column_unit: ColumnUnit,
+    /// Whether the first line and column are 1 instead of 0
+    pub one_based: bool
+}
+
+impl PositionOptions {
+    fn base(&self) -> usize {
+        if self.one_based { 1 } else { 0 }
+    }
+}
+
+/// Tracks the line and column of a cursor moving forward through a text.
+#[derive(Debug, Clone)]
+pub struct PositionTracker {
+    line: usize,
+    column: usize,
+    options: PositionOptions
+}
+
+impl PositionTracker {
+    pub fn new(options: PositionOptions) -> Self {
+        PositionTracker { line: 0, column: 0, options }
+    }
+
+    /// The line of the cursor, in the configured base.
+    pub fn line(&self) -> usize {
+        self.line + self.options.base()
+    }
+
+    /// The column of the cursor, in the configured unit and base.
+    pub fn column(&self) -> usize {
+        self.column + self.options.base()
+    }
+
+    /// Moves the cursor past a piece of text. A `\n` ends the line it is on,
+    /// so it is counted as the last column of that line.
+    pub fn advance(&mut self, piece: &str) {
+        for (i, line) in piece.split('\n').enumerate() {
+            if i > 0 {
+                self.line += 1;
+                self.column = 0;
+            }
+            self.column += self.options.column_unit.width(line);
+        }
+    }
+}
+
+/// Resolves byte offsets of a text to lines and columns after the fact, e.g.
+/// for tokens that were merged by `Wrap` or `combine`.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+    options: PositionOptions
+}
+
+impl LineIndex {
+    pub fn new(text: &str, options: PositionOptions) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex { line_starts, options }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The byte offset at which a (zero-based) line starts.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.line_starts.get(line).copied()
+    }
+
+    /// The line and column of a byte offset of `text`, which must be the text
+    /// this index was built from. Offsets past the end of the text are clamped.
+    pub fn position(&self, text: &str, offset: usize) -> (usize, usize) {
+        let mut offset = offset.min(text.len());
+        while !text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let column = self.options.column_unit.width(&text[self.line_starts[line]..offset]);
+        (line + self.options.base(), column + self.options.base())
+    }
+
+    /// The byte offset of a line and column, the inverse of `position`. A
+    /// column past the end of its line is clamped to the end of the line, and
+    /// a line past the end of the text to the end of the text.
+    pub fn offset(&self, text: &str, line: usize, column: usize) -> usize {
+        let line = line.saturating_sub(self.options.base());
+        let Some(&start) = self.line_starts.get(line) else {
+            return text.len();
+        };
+        let end = self.line_starts.get(line + 1).map_or(text.len(), |&next| next - 1);
+        let mut remaining = column.saturating_sub(self.options.base());
+        for (i, c) in text[start..end].char_indices() {
+            let width = self.options.column_unit.width(c.encode_utf8(&mut [0; 4]));
+            if remaining < width {
+                return start + i;
+            }
+            remaining -= width;
+        }
+        end
+    }
+}
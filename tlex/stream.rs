@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, ErrorKind, Read};
+use std::ops::Range;
+use std::sync::Arc;
+
+use super::lexer::*;
+use super::position::*;
+use super::token::*;
+use crate::RustuckError;
+
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A token lexed from a stream. `token` is relative to the chunk of input it
+/// was lexed in, which is its `source`, while `offset` places that chunk in
+/// the whole input. Lines and columns are already relative to the whole
+/// input.
+#[derive(Debug, Clone)]
+pub struct StreamedToken {
+    pub offset: usize,
+    pub token: OwnedToken
+}
+
+impl StreamedToken {
+    /// The byte range of the token in the whole input.
+    pub fn location(&self) -> Range<usize> {
+        self.offset + self.token.location.start..self.offset + self.token.location.end
+    }
+}
+
+/// Lexes input that arrives in pieces, keeping only the tokens of one chunk
+/// in memory at a time.
+///
+/// Input is cut into chunks of at least the chunk size that end at the end of
+/// a line, and each chunk is lexed on its own. Lexing a stream gives the same
+/// tokens as lexing the whole input as long as no token of the lexer spans a
+/// line break; the token at the very end of the input is only added by
+/// `finish`. A line longer than the maximum chunk size is cut at a character
+/// boundary instead, which may split a token.
+pub struct StreamLexer<'l> {
+    lexer: &'l Lexer<'l>,
+    file: Arc<str>,
+    options: PositionOptions,
+    chunk_size: usize,
+    max_chunk_size: usize,
+    // input that has not been lexed yet, starting at `offset` on `line`,
+    // `column` into it
+    pending: String,
+    offset: usize,
+    line: usize,
+    column: usize
+}
+
+impl<'l> StreamLexer<'l> {
+    pub fn new(lexer: &'l Lexer<'l>, file_name: &str) -> Self {
+        StreamLexer {
+            lexer,
+            file: file_name.into(),
+            options: PositionOptions::default(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            pending: String::new(),
+            offset: 0,
+            line: 0,
+            column: 0
+        }
+    }
+
+    pub fn with_options(mut self, options: PositionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sets the number of bytes to collect before lexing a chunk.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Sets the number of bytes after which a line is cut into chunks.
+    pub fn with_max_chunk_size(mut self, max_chunk_size: usize) -> Self {
+        // room for a whole character
+        self.max_chunk_size = max_chunk_size.max(4);
+        self
+    }
+
+    /// Adds input, returning the tokens of every chunk it completed.
+    pub fn push(&mut self, text: &str) -> Result<Vec<StreamedToken>, RustuckError> {
+        self.pending.push_str(text);
+        let mut tokens = vec![];
+        while self.pending.len() >= self.chunk_size {
+            let end = match self.pending.rfind('\n') {
+                Some(end) => end + 1,
+                None if self.pending.len() >= self.max_chunk_size => {
+                    (1..=self.max_chunk_size).rev()
+                        .find(|&end| self.pending.is_char_boundary(end))
+                        .unwrap_or(self.pending.len())
+                }
+                None => break
+            };
+            tokens.extend(self.lex_chunk(end, false)?);
+        }
+        Ok(tokens)
+    }
+
+    /// Lexes the rest of the input, returning its tokens followed by the
+    /// token marking the end of the input.
+    pub fn finish(&mut self) -> Result<Vec<StreamedToken>, RustuckError> {
+        self.lex_chunk(self.pending.len(), true)
+    }
+
+    /// Lexes everything read from `reader`, yielding tokens as their chunks
+    /// are completed.
+    pub fn tokens<R: BufRead>(self, reader: R) -> TokenStream<'l, R> {
+        TokenStream { lexer: self, reader, bytes: vec![], ready: VecDeque::new(), done: false }
+    }
+
+    fn lex_chunk(&mut self, end: usize, last: bool) -> Result<Vec<StreamedToken>, RustuckError> {
+        let source: Arc<str> = self.pending[..end].into();
+        self.pending.drain(..end);
+
+        let mut code = to_tokens_with(&source, &self.file, self.options);
+        if !last {
+            code.pop();
+        }
+        self.lexer.lex(&mut code, &mut ())?;
+
+        let shared = &mut SharedStrs::default();
+        shared.share(&source);
+        shared.share(&self.file);
+        let first_line = if self.options.one_based { 1 } else { 0 };
+        let tokens = code.iter()
+            .map(|token| {
+                let mut token = token.to_owned_with(shared);
+                if token.line == first_line {
+                    token.char += self.column;
+                }
+                token.line += self.line;
+                StreamedToken { offset: self.offset, token }
+            })
+            .collect();
+
+        self.offset += source.len();
+        self.line += source.matches('\n').count();
+        match source.rfind('\n') {
+            Some(end) => self.column = self.options.column_unit.width(&source[end + 1..]),
+            None => self.column += self.options.column_unit.width(&source)
+        }
+        Ok(tokens)
+    }
+}
+
+/// An iterator over the tokens lexed from a reader.
+pub struct TokenStream<'l, R> {
+    lexer: StreamLexer<'l>,
+    reader: R,
+    // bytes read but not pushed yet, as they end inside a character
+    bytes: Vec<u8>,
+    ready: VecDeque<StreamedToken>,
+    done: bool
+}
+
+impl<R: BufRead> TokenStream<'_, R> {
+    fn fill(&mut self) -> Result<(), RustuckError> {
+        let invalid = || RustuckError::Io {
+            kind: ErrorKind::InvalidData,
+            message: "stream did not contain valid UTF-8".to_string()
+        };
+        while !self.done && self.ready.is_empty() {
+            // a line is read at most a chunk at a time, so that a long line
+            // is never buffered whole
+            let limit = self.lexer.max_chunk_size as u64;
+            let read = (&mut self.reader).take(limit).read_until(b'\n', &mut self.bytes)
+                .map_err(|e| RustuckError::Io { kind: e.kind(), message: e.to_string() })?;
+            if read == 0 {
+                if !self.bytes.is_empty() {
+                    return Err(invalid());
+                }
+                self.done = true;
+                self.ready.extend(self.lexer.finish()?);
+                continue;
+            }
+
+            let valid = match std::str::from_utf8(&self.bytes) {
+                Ok(text) => text.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(_) => return Err(invalid())
+            };
+            let text = std::str::from_utf8(&self.bytes[..valid]).map_err(|_| invalid())?;
+            let tokens = self.lexer.push(text)?;
+            self.bytes.drain(..valid);
+            self.ready.extend(tokens);
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for TokenStream<'_, R> {
+    type Item = Result<StreamedToken, RustuckError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill() {
+            self.done = true;
+            self.ready.clear();
+            return Some(Err(e));
+        }
+        self.ready.pop_front().map(Ok)
+    }
+}
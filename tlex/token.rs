@@ -1,11 +1,16 @@
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::ops::Range;
+use std::sync::Arc;
+
+use super::position::*;
+use crate::{TagId, TagSet};
 
 #[derive(Debug, Clone)]
 pub struct Token<'a> {
     pub location: Range<usize>,
     pub body: &'a str,
-    pub tags: Vec<&'a str>,
+    pub tags: TagSet,
     pub line: usize,
     pub char: usize,
     pub file: &'a str
@@ -23,6 +28,81 @@ impl Display for Token<'_> {
     }
 }
 
+/// A `Token` that holds a shared copy of its source and file name instead of
+/// borrowing them, so it can be stored in long-lived structures or sent to 
+/// other threads.
+#[derive(Debug, Clone)]
+pub struct OwnedToken {
+    pub location: Range<usize>,
+    pub source: Arc<str>,
+    pub tags: TagSet,
+    pub line: usize,
+    pub char: usize,
+    pub file: Arc<str>
+}
+
+impl OwnedToken {
+    pub fn content(&self) -> &str {
+        &self.source[self.location.start..self.location.end]
+    }
+
+    /// A borrowed view of the token.
+    pub fn as_token(&self) -> Token<'_> {
+        Token {
+            location: self.location.clone(),
+            body: &self.source,
+            tags: self.tags.clone(),
+            line: self.line,
+            char: self.char,
+            file: &self.file
+        }
+    }
+}
+
+impl Display for OwnedToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_token().fmt(f)
+    }
+}
+
+/// Hands out one `Arc<str>` per distinct borrowed string, so owned tokens 
+/// made from the same body or file name share it.
+#[derive(Default)]
+pub(crate) struct SharedStrs {
+    strs: HashMap<(*const u8, usize), Arc<str>>
+}
+
+impl SharedStrs {
+    pub(crate) fn get(&mut self, s: &str) -> Arc<str> {
+        self.strs.entry((s.as_ptr(), s.len())).or_insert_with(|| s.into()).clone()
+    }
+
+    /// Makes strings borrowed from `s` use it instead of a copy.
+    pub(crate) fn share(&mut self, s: &Arc<str>) {
+        self.strs.insert((s.as_ptr(), s.len()), s.clone());
+    }
+}
+
+impl Token<'_> {
+    pub(crate) fn to_owned_with(&self, shared: &mut SharedStrs) -> OwnedToken {
+        OwnedToken {
+            location: self.location.clone(),
+            source: shared.get(self.body),
+            tags: self.tags.clone(),
+            line: self.line,
+            char: self.char,
+            file: shared.get(self.file)
+        }
+    }
+}
+
+/// Copies tokens into owned ones. Tokens with the same body share one copy of
+/// it.
+pub fn to_owned_tokens(tokens: &[Token]) -> Vec<OwnedToken> {
+    let shared = &mut SharedStrs::default();
+    tokens.iter().map(|token| token.to_owned_with(shared)).collect()
+}
+
 pub fn print_tokens(tokens: &[Token]) {
     for token in tokens.iter() {
         println!("{}", token);
@@ -31,42 +111,79 @@ pub fn print_tokens(tokens: &[Token]) {
     
 pub fn to_tokens<'a>(text: &'a str, file_name : &'a str) -> Vec<Token<'a>>
 {
-    let mut char_index: usize = 0;
-    let mut line_index: usize = 0;
+    to_tokens_with(text, file_name, PositionOptions::default())
+}
+
+/// Like `to_tokens`, but with lines and columns reported as described by 
+/// `options`.
+pub fn to_tokens_with<'a>(
+    text: &'a str, 
+    file_name: &'a str, 
+    options: PositionOptions
+) -> Vec<Token<'a>>
+{
+    to_tokens_by(text, file_name, options, text.char_indices().map(|(i, ch)| &text[i..i + ch.len_utf8()]))
+}
+
+/// Like `to_tokens`, but produces one token per extended grapheme cluster 
+/// instead of one per Unicode scalar, so combining marks and multi-scalar 
+/// emoji stay attached to their base character.
+#[cfg(feature = "graphemes")]
+pub fn to_grapheme_tokens<'a>(text: &'a str, file_name : &'a str) -> Vec<Token<'a>>
+{
+    to_grapheme_tokens_with(text, file_name, PositionOptions::default())
+}
+
+#[cfg(feature = "graphemes")]
+pub fn to_grapheme_tokens_with<'a>(
+    text: &'a str, 
+    file_name: &'a str, 
+    options: PositionOptions
+) -> Vec<Token<'a>>
+{
+    use unicode_segmentation::UnicodeSegmentation;
+
+    to_tokens_by(text, file_name, options, text.graphemes(true))
+}
+
+// Every piece must be a subslice of `text`, yielded in order.
+fn to_tokens_by<'a>(
+    text: &'a str, 
+    file_name: &'a str, 
+    options: PositionOptions,
+    pieces: impl Iterator<Item = &'a str>
+) -> Vec<Token<'a>>
+{
+    let mut position = PositionTracker::new(options);
     let mut tokens : Vec<Token> = vec![];
+    // the ids of the pieces seen so far, to look each one up only once
+    let mut ids: HashMap<&str, TagId> = HashMap::new();
+    let ws = TagId::intern("ws");
+
+    for c in pieces {
+        let i = c.as_ptr() as usize - text.as_ptr() as usize;
+        let mut tags = TagSet::from_iter([*ids.entry(c).or_insert_with(|| TagId::intern(c))]);
+        if c.chars().all(char::is_whitespace) {
+            tags.insert(ws);
+        }
 
-    for i in 0..text.len() {
-        let c = &text[i..=i];
         tokens.push(Token {
-            location: i..i + 1,
+            location: i..i + c.len(),
             body: text,
-            tags: vec![c],
-            line: line_index,
-            char: char_index,
+            tags,
+            line: position.line(),
+            char: position.column(),
             file: file_name
         });
-
-        if let Some(ch) = c.chars().next() {
-            let len = tokens.len();
-
-            if ch.is_whitespace() {
-                tokens[len - 1].tags.push("ws");
-            } 
-        }
-
-        char_index += 1;
-        if text.chars().nth(char_index) == Some('\n') {
-            char_index = 0;
-            line_index += 1;
-        }
+        position.advance(c);
     }
 
     tokens.push(Token {
-        location: 0..0,
+        location: text.len()..text.len(),
         body: text,
-        tags: vec![" ", "ws"],
-        line: line_index,
-        char: char_index,
+        tags: TagSet::from_iter([TagId::intern(" "), ws]),
+        line: position.line(),
+        char: position.column(),
         file: file_name
     });
 
@@ -0,0 +1,134 @@
+use std::fmt::Display;
+
+use super::lexer::*;
+
+/// A problem found in a routine before running it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub routine: String,
+    /// The index of the offending instruction, preceded by the indices of the
+    /// `Block`s it is nested in
+    pub path: Vec<usize>,
+    pub kind: ValidationErrorKind
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationErrorKind {
+    /// A `Goto` names a label that it cannot reach
+    UndefinedLabel(String),
+    /// The same label is defined twice in one block
+    DuplicateLabel(String),
+    /// An `Else` that does not follow an `If` and its body
+    ElseWithoutIf,
+    /// An `If` or `Else` with no instruction after it
+    MissingBody,
+    /// A `Wrap` that always runs with the cursor on the token the cycle
+    /// started on
+    WrapUnderflow
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path: Vec<String> = self.path.iter().map(usize::to_string).collect();
+        write!(f, "routine {}, instruction {}: ", self.routine, path.join("."))?;
+        match &self.kind {
+            ValidationErrorKind::UndefinedLabel(label) => write!(f, "Goto to undefined label \"{}\"", label),
+            ValidationErrorKind::DuplicateLabel(label) => write!(f, "label \"{}\" is defined more than once", label),
+            ValidationErrorKind::ElseWithoutIf => write!(f, "Else does not follow an If"),
+            ValidationErrorKind::MissingBody => write!(f, "nothing to run after the condition"),
+            ValidationErrorKind::WrapUnderflow => write!(f, "Wrap with nothing to wrap")
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl Lexer<'_> {
+    /// Checks every routine of the lexer, reporting all problems found.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let errors: Vec<ValidationError> = self.rules.iter()
+            .filter_map(|routine| routine.validate().err())
+            .flatten()
+            .collect();
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+impl Routine<'_> {
+    /// Checks the instructions of the routine, reporting all problems found.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+        validate_block(self.name, self.instrs(), &mut vec![], &mut vec![], Some(0), &mut errors);
+        errors.sort_by(|a, b| a.path.cmp(&b.path));
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+// `scopes` holds the labels of every enclosing block. `offset` is how far the 
+// cursor is known to be past the start of the cycle, or None once branches or
+// jumps make it unknowable.
+fn validate_block<'r>(
+    routine: &str,
+    instrs: &[Instruction<'r>],
+    path: &mut Vec<usize>,
+    scopes: &mut Vec<Vec<&'r str>>,
+    mut offset: Option<usize>,
+    errors: &mut Vec<ValidationError>
+) {
+    let report = |errors: &mut Vec<ValidationError>, path: &Vec<usize>, i: usize, kind| {
+        let mut path = path.clone();
+        path.push(i);
+        errors.push(ValidationError { routine: routine.to_string(), path, kind });
+    };
+    let mut labels: Vec<&str> = vec![];
+    for (i, instr) in instrs.iter().enumerate() {
+        if let Instruction::Label(label) = instr {
+            if labels.contains(label) {
+                report(errors, path, i, ValidationErrorKind::DuplicateLabel(label.to_string()));
+            }
+            labels.push(label);
+        }
+    }
+    scopes.push(labels);
+
+    for (i, instr) in instrs.iter().enumerate() {
+        match instr {
+            Instruction::If(_) | Instruction::Else if i + 1 >= instrs.len() =>
+                report(errors, path, i, ValidationErrorKind::MissingBody),
+            _ => {}
+        }
+
+        match instr {
+            Instruction::Next => offset = offset.map(|o| o + 1),
+            Instruction::Back => offset = offset.map(|o| o.saturating_sub(1)),
+            Instruction::Wrap => {
+                if offset == Some(0) {
+                    report(errors, path, i, ValidationErrorKind::WrapUnderflow);
+                }
+                offset = Some(1);
+            }
+            Instruction::Else => {
+                if i < 2 || !matches!(instrs[i - 2], Instruction::If(_)) {
+                    report(errors, path, i, ValidationErrorKind::ElseWithoutIf);
+                }
+                offset = None;
+            }
+            Instruction::If(_) | Instruction::Delete => offset = None,
+            Instruction::Label(_) => offset = None,
+            Instruction::Goto(label) => {
+                if !scopes.iter().any(|labels| labels.contains(label)) {
+                    report(errors, path, i, ValidationErrorKind::UndefinedLabel(label.to_string()));
+                }
+                offset = None;
+            }
+            // the cursor of a block does not carry over to the instructions after it
+            Instruction::Block(inside) => {
+                path.push(i);
+                validate_block(routine, inside, path, scopes, offset, errors);
+                path.pop();
+            }
+            Instruction::Add(_) | Instruction::Skip | Instruction::Cancel => {}
+        }
+    }
+    scopes.pop();
+}
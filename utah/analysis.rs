@@ -0,0 +1,238 @@
+use std::fmt::Display;
+
+use super::observer::*;
+use super::parse_token::ParseToken;
+use super::parser::*;
+
+/// A possible problem with the order of the rules of a parser, found by
+/// `Parser::analyze`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleConflict {
+    /// The index of the later rule involved
+    pub rule: usize,
+    pub kind: RuleConflictKind
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleConflictKind {
+    /// Some tokens can be matched by both this rule and the earlier
+    /// `other`, starting at the same token, so which one applies depends on
+    /// the order of the rules
+    Overlap { other: usize },
+    /// Every match of this rule contains a match of the earlier rule `by`,
+    /// which combines those tokens first, so this rule never fires
+    Shadowed { by: usize },
+    /// `;;` rules that only add tags to tokens with the tags the other rules
+    /// add, in a cycle; `rules` holds every rule of the cycle
+    RelabelCycle { rules: Vec<usize> }
+}
+
+impl Display for RuleConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rule {}: ", self.rule)?;
+        match &self.kind {
+            RuleConflictKind::Overlap { other } => write!(f, "matches some of the same tokens as rule {}", other),
+            RuleConflictKind::Shadowed { by } => write!(f, "never fires, as rule {} always combines part of its match first", by),
+            RuleConflictKind::RelabelCycle { rules } => {
+                let rules: Vec<String> = rules.iter().map(usize::to_string).collect();
+                write!(f, "rules {} relabel each other in a cycle", rules.join(", "))
+            }
+        }
+    }
+}
+
+impl Parser<'_> {
+    /// Finds rules whose effect depends on their order: rules that overlap an
+    /// earlier rule, rules shadowed by an earlier rule, and cycles of `;;`
+    /// rules.
+    ///
+    /// Only an earlier rule that combines tokens can take tokens from a later
+    /// one, as `;;` rules leave the tokens in place. Tags are compared by
+    /// name: two tags can be on one token if they are the same, if `;;`
+    /// rules add one to tokens with the other, or if one rule adds both. The
+    /// tags the lexer adds together are not known here.
+    pub fn analyze(&self) -> Vec<RuleConflict> {
+        let mut conflicts = vec![];
+        for (later, rule) in self.rules().iter().enumerate() {
+            for (earlier, other) in self.rules()[..later].iter().enumerate() {
+                if other.add_all {
+                    continue;
+                }
+                let kind = if self.shadows(earlier, rule) {
+                    RuleConflictKind::Shadowed { by: earlier }
+                }
+                else if self.overlaps(other, rule) {
+                    RuleConflictKind::Overlap { other: earlier }
+                }
+                else {
+                    continue;
+                };
+                conflicts.push(RuleConflict { rule: later, kind });
+            }
+        }
+
+        for cycle in self.relabel_cycles() {
+            conflicts.push(RuleConflict { rule: *cycle.last().unwrap(), kind: RuleConflictKind::RelabelCycle { rules: cycle } });
+        }
+        conflicts.sort_by_key(|conflict| conflict.rule);
+        conflicts
+    }
+
+    // Whether rule `by` matches inside every sequence of tags that `rule`
+    // matches, running it on tokens that have exactly those tags, and the
+    // tags the `;;` rules before it add to them.
+    fn shadows(&self, by: usize, rule: &Rule<'_>) -> bool {
+        // whether a rule with conditions matches depends on the tokens around,
+        // and a token `rule` matches may have any tag besides those it needs
+        let shadowing = &self.rules()[by];
+        if !shadowing.context.is_empty() || shadowing.matches.iter().any(|m| matches!(m, Match::Not(_))) {
+            return false;
+        }
+        let before = &self.rules()[..by];
+        words(rule).iter().flat_map(|word| alternatives(word)).all(|word| {
+            // each token spans a character, as `_` and `!` only match tokens
+            // that are not empty
+            let body = " ".repeat(word.len());
+            let mut code: Vec<ParseToken> = word.iter().enumerate().map(|(i, tag)| ParseToken {
+                location: i..i + 1,
+                body: &body,
+                tags: tag.map(|tag| implied(before, tag)).unwrap_or_default().into_iter().collect(),
+                children: vec![],
+                line: 0,
+                char: i,
+                file: ""
+            }).collect();
+            let mut matched = MatchObserver(false);
+            self.rules()[by].traverse(&mut code, &mut false, &mut matched);
+            matched.0
+        })
+    }
+
+    fn overlaps(&self, earlier: &Rule<'_>, rule: &Rule<'_>) -> bool {
+        let (earlier_words, words) = (words(earlier), words(rule));
+        earlier_words.iter().any(|a| words.iter().any(|b| {
+            a.iter().zip(b).all(|(x, y)| self.both_match(x, y))
+        }))
+    }
+
+    // Whether a token can be matched by both matches.
+    fn both_match(&self, a: &Match<'_>, b: &Match<'_>) -> bool {
+        match (a, b) {
+            (Match::Any, _) | (_, Match::Any) | (Match::Not(_), Match::Not(_)) => true,
+            (Match::Not(not), other) | (other, Match::Not(not)) =>
+                other.tags().iter().any(|tag| !implied(self.rules(), tag).contains(not)),
+            _ => a.tags().iter().any(|x| b.tags().iter().any(|y| self.compatible(x, y)))
+        }
+    }
+
+    // Whether a token can have both tags.
+    fn compatible(&self, a: &str, b: &str) -> bool {
+        if a == b {
+            return true;
+        }
+        let both = |origin: &[&str]| {
+            let tags: Vec<&str> = origin.iter().flat_map(|tag| implied(self.rules(), tag)).collect();
+            tags.contains(&a) && tags.contains(&b)
+        };
+        let mut origins: Vec<Vec<&str>> = self.rules().iter()
+            .filter(|rule| !rule.add_all)
+            .map(|rule| rule.tags.clone())
+            .collect();
+        origins.extend(self.rules().iter().flat_map(|rule| rule.matches.iter().flat_map(Match::tags).chain(&rule.tags)).map(|tag| vec![*tag]));
+        origins.iter().any(|origin| both(origin))
+    }
+
+    // The `;;` rules that add a tag to tokens with a tag that the tag they
+    // add leads back to, grouped by cycle.
+    fn relabel_cycles(&self) -> Vec<Vec<usize>> {
+        // a tag of each rule that is on a cycle
+        let on_cycle: Vec<(usize, &str)> = self.rules().iter().enumerate()
+            .filter(|(_, rule)| rule.add_all)
+            .filter_map(|(i, rule)| {
+                rule.matches.iter().flat_map(Match::tags)
+                    .find(|m| rule.tags.iter().any(|tag| implied(self.rules(), tag).contains(m)))
+                    .map(|m| (i, *m))
+            })
+            .collect();
+
+        let mut cycles: Vec<Vec<usize>> = vec![];
+        for (i, tag) in &on_cycle {
+            if cycles.iter().any(|cycle| cycle.contains(i)) {
+                continue;
+            }
+            let same_cycle = |other: &str| implied(self.rules(), tag).contains(&other) && implied(self.rules(), other).contains(tag);
+            cycles.push(on_cycle.iter().filter(|(_, other)| same_cycle(other)).map(|(j, _)| *j).collect());
+        }
+        cycles
+    }
+}
+
+// Whether a rule matched.
+struct MatchObserver(bool);
+
+impl ParseObserver for MatchObserver {
+    fn observe(&mut self, event: &ParseEvent<'_>) {
+        if let ParseEvent::RuleMatched { .. } = event {
+            self.0 = true;
+        }
+    }
+}
+
+// The sequences of matches of a rule, with its repeated match, if any,
+// taken zero, one and two times.
+fn words<'a>(rule: &Rule<'a>) -> Vec<Vec<Match<'a>>> {
+    let Some(repeat) = rule.repeat else {
+        return vec![rule.matches.clone()];
+    };
+    (0..3).map(|times| {
+        let mut word = rule.matches[..repeat].to_vec();
+        word.extend(std::iter::repeat_n(rule.matches[repeat].clone(), times));
+        word.extend_from_slice(&rule.matches[repeat + 1..]);
+        word
+    })
+    .filter(|word| !word.is_empty())
+    .collect()
+}
+
+// The sequences of tags the tokens of a word are sure to have, one for each
+// choice among the alternatives of its matches, with `None` for a token
+// that needs no tag.
+fn alternatives<'a>(word: &[Match<'a>]) -> Vec<Vec<Option<&'a str>>> {
+    word.iter().fold(vec![vec![]], |sequences, m| {
+        let choices: Vec<Option<&str>> = match m.tags() {
+            [] => vec![None],
+            tags => tags.iter().copied().map(Some).collect()
+        };
+        sequences.iter()
+            .flat_map(|sequence| choices.iter().map(move |choice| {
+                let mut sequence = sequence.clone();
+                sequence.push(*choice);
+                sequence
+            }))
+            .collect()
+    })
+}
+
+// The tag and every tag that the `;;` rules among `rules` add to a token
+// with it, directly or through each other.
+fn implied<'a>(rules: &[Rule<'a>], tag: &'a str) -> Vec<&'a str> {
+    let mut tags = vec![tag];
+    let mut i = 0;
+    while i < tags.len() {
+        let current = tags[i];
+        for rule in rules.iter().filter(|rule| rule.add_all && rule.matches.iter().any(|m| implies(m, current))) {
+            for added in &rule.tags {
+                if !tags.contains(added) {
+                    tags.push(added);
+                }
+            }
+        }
+        i += 1;
+    }
+    tags
+}
+
+// Whether the match accepts every token with the tag.
+fn implies(m: &Match<'_>, tag: &str) -> bool {
+    matches!(m, Match::Any) || m.tags().contains(&tag)
+}
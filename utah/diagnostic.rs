@@ -0,0 +1,156 @@
+use std::fmt::Display;
+use std::ops::Range;
+
+use super::parse_token::ParseToken;
+use super::parser::{Match, Parser, Rule};
+use crate::TagSet;
+
+/// A run of top level tokens that the parser did not reduce to its goal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDiagnostic<'a> {
+    /// The bytes the run spans
+    pub location: Range<usize>,
+    /// The indices of the run in the parsed tokens
+    pub tokens: Range<usize>,
+    pub text: &'a str,
+    /// The source the tokens were lexed from
+    pub body: &'a str,
+    pub line: usize,
+    pub char: usize,
+    pub file: &'a str,
+    /// The rules that matched the most tokens before failing on or just
+    /// after the run
+    pub near_misses: Vec<NearMiss<'a>>
+}
+
+/// A rule that matched the start of its pattern and then failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NearMiss<'a> {
+    /// The index of the rule in the parser
+    pub rule: usize,
+    pub matches: Vec<Match<'a>>,
+    /// How many tokens the rule matched before failing
+    pub matched: usize,
+    /// The match the rule needed next
+    pub expected: Match<'a>,
+    /// The tags of the token it found instead, or `None` at the end of the
+    /// input
+    pub found: Option<TagSet>
+}
+
+impl<'r> Parser<'r> {
+    /// Reports the runs of top level tokens that do not have the goal tag,
+    /// leaving out whitespace and empty tokens at their ends. Reports nothing
+    /// if the parser has no goal.
+    pub fn diagnose<'a>(&self, code: &[ParseToken<'a>]) -> Vec<ParseDiagnostic<'a>> where 'r: 'a {
+        match self.goal {
+            Some(goal) => self.diagnose_against(code, goal),
+            None => vec![]
+        }
+    }
+
+    // Like `diagnose`, with the given goal instead of the parser's.
+    pub(crate) fn diagnose_against<'a>(&self, code: &[ParseToken<'a>], goal: &str) -> Vec<ParseDiagnostic<'a>> where 'r: 'a {
+        let ignored = |pt: &ParseToken| pt.location.is_empty() || pt.tags.has("ws");
+
+        let mut diagnostics = vec![];
+        let mut index = 0;
+        while index < code.len() {
+            if code[index].tags.has(goal) || ignored(&code[index]) {
+                index += 1;
+                continue;
+            }
+            let start = index;
+            let mut end = index + 1;
+            while end < code.len() && !code[end].tags.has(goal) {
+                end += 1;
+            }
+            index = end;
+            while ignored(&code[end - 1]) {
+                end -= 1;
+            }
+
+            let (first, last) = (&code[start], &code[end - 1]);
+            let location = first.location.start..last.location.end;
+            diagnostics.push(ParseDiagnostic {
+                text: &first.body[location.clone()],
+                location,
+                tokens: start..end,
+                body: first.body,
+                line: first.line,
+                char: first.char,
+                file: first.file,
+                near_misses: self.near_misses(code, start..end)
+            });
+        }
+        diagnostics
+    }
+
+    fn near_misses<'a>(&self, code: &[ParseToken], run: Range<usize>) -> Vec<NearMiss<'a>> where 'r: 'a {
+        let mut misses: Vec<NearMiss<'a>> = vec![];
+        for (rule_index, rule) in self.rules().iter().enumerate() {
+            let earliest = run.start.saturating_sub(rule.matches.len());
+            let best = (earliest..run.end)
+                .filter_map(|start| {
+                    let (matched, next) = partial_match(rule, code, start);
+                    let failed_at = start + matched;
+                    (matched > 0 && next < rule.matches.len() && run.start <= failed_at && failed_at <= run.end)
+                        .then_some((matched, next, failed_at))
+                })
+                .max_by_key(|&(matched, _, _)| matched);
+            if let Some((matched, next, failed_at)) = best {
+                misses.push(NearMiss {
+                    rule: rule_index,
+                    matches: rule.matches.clone(),
+                    matched,
+                    expected: rule.matches[next].clone(),
+                    found: code.get(failed_at).filter(|pt| !pt.location.is_empty()).map(|pt| pt.tags.clone())
+                });
+            }
+        }
+        let most = misses.iter().map(|miss| miss.matched).max().unwrap_or_default();
+        misses.retain(|miss| miss.matched == most);
+        misses
+    }
+}
+
+// How far a rule matches the tokens from `start`: the number of tokens it
+// matched, and the index of the match it stopped at.
+fn partial_match(rule: &Rule, code: &[ParseToken], start: usize) -> (usize, usize) {
+    let (mut matched, mut index_in_rule) = (0, 0);
+    while index_in_rule < rule.matches.len() {
+        let found = code.get(start + matched).is_some_and(|pt| rule.matches[index_in_rule].accepts(pt));
+        if found {
+            matched += 1;
+            if Some(index_in_rule) != rule.repeat {
+                index_in_rule += 1;
+            }
+        } else if Some(index_in_rule) == rule.repeat {
+            index_in_rule += 1;
+        } else {
+            break;
+        }
+    }
+    (matched, index_in_rule)
+}
+
+impl Display for NearMiss<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let matches: Vec<String> = self.matches.iter().map(|m| m.to_string()).collect();
+        write!(f, "rule {} ({}) expected {} after {} token(s)", self.rule, matches.join(" "), self.expected, self.matched)?;
+        match &self.found {
+            Some(tags) => write!(f, ", found {:?}", tags),
+            None => write!(f, ", found the end of the input")
+        }
+    }
+}
+
+impl Display for ParseDiagnostic<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}: could not parse {:?}", self.file, self.line, self.char, self.text)?;
+        for miss in &self.near_misses {
+            write!(f, "\n  closest: {}", miss)?;
+        }
+        Ok(())
+    }
+}
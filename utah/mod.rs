@@ -1,2 +1,6 @@
+pub mod analysis;
+pub mod diagnostic;
+pub mod observer;
 pub mod parse_token;
-pub mod parser;
\ No newline at end of file
+pub mod parser;
+pub mod pratt;
\ No newline at end of file
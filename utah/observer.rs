@@ -0,0 +1,38 @@
+use super::parse_token::ParseToken;
+use super::parser::Rule;
+use crate::PrintObserver;
+
+/// Something that happened while a parser was running.
+#[derive(Debug)]
+pub enum ParseEvent<'e> {
+    /// A pass over every rule started; `depth` counts the passes before it
+    PassStarted { depth: usize },
+    /// `rule` matched the tokens in `start..end`
+    RuleMatched { rule: &'e Rule<'e>, start: usize, end: usize },
+    /// Several tokens were combined into `token`
+    TokensCombined { token: &'e ParseToken<'e> },
+    TagAdded { tag: &'e str, token: &'e ParseToken<'e> }
+}
+
+/// Receives the events of a running parser, e.g. to log them, collect
+/// metrics or step through the passes.
+pub trait ParseObserver {
+    fn observe(&mut self, event: &ParseEvent<'_>);
+}
+
+/// Ignores every event.
+impl ParseObserver for () {
+    fn observe(&mut self, _event: &ParseEvent<'_>) {}
+}
+
+impl ParseObserver for PrintObserver {
+    fn observe(&mut self, event: &ParseEvent<'_>) {
+        match event {
+            ParseEvent::PassStarted { depth } => println!("Entering depth {}", depth),
+            ParseEvent::RuleMatched { rule, start, end } =>
+                println!("Rule {:?} ; {:?} matched tokens {}..{}.", rule.matches, rule.tags, start, end),
+            ParseEvent::TokensCombined { token } => print!("Combined into {}", token),
+            ParseEvent::TagAdded { tag, token } => print!("Added the tag \"{0}\" to {1}", tag, token)
+        }
+    }
+}
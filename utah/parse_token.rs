@@ -1,12 +1,14 @@
 use std::fmt::Display;
 use std::ops::Range;
-use crate::Token;
+use std::sync::Arc;
+use crate::tlex::token::SharedStrs;
+use crate::{TagSet, Token};
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct ParseToken<'a> {
     pub location: Range<usize>,
     pub body: &'a str,
-    pub tags: Vec<&'a str>,
+    pub tags: TagSet,
     pub children: Vec<ParseToken<'a>>,
     pub line: usize,
     pub char: usize,
@@ -14,9 +16,67 @@ pub struct ParseToken<'a> {
 }
 
 impl ParseToken<'_> {
-    fn content(&self) -> &str {
+    pub fn content(&self) -> &str {
         &self.body[self.location.start..self.location.end]
     }
+
+    pub(crate) fn to_owned_with(&self, shared: &mut SharedStrs) -> OwnedParseToken {
+        OwnedParseToken {
+            location: self.location.clone(),
+            source: shared.get(self.body),
+            tags: self.tags.clone(),
+            children: self.children.iter().map(|child| child.to_owned_with(shared)).collect(),
+            line: self.line,
+            char: self.char,
+            file: shared.get(self.file)
+        }
+    }
+}
+
+/// A `ParseToken` tree that holds a shared copy of its source and file name
+/// instead of borrowing them, so it can be stored in long-lived structures,
+/// cached or sent to other threads.
+#[derive(Debug, Clone)]
+pub struct OwnedParseToken {
+    pub location: Range<usize>,
+    pub source: Arc<str>,
+    pub tags: TagSet,
+    pub children: Vec<OwnedParseToken>,
+    pub line: usize,
+    pub char: usize,
+    pub file: Arc<str>
+}
+
+impl OwnedParseToken {
+    pub fn content(&self) -> &str {
+        &self.source[self.location.start..self.location.end]
+    }
+
+    /// A borrowed view of the tree.
+    pub fn as_parse_token(&self) -> ParseToken<'_> {
+        ParseToken {
+            location: self.location.clone(),
+            body: &self.source,
+            tags: self.tags.clone(),
+            children: self.children.iter().map(OwnedParseToken::as_parse_token).collect(),
+            line: self.line,
+            char: self.char,
+            file: &self.file
+        }
+    }
+}
+
+impl Display for OwnedParseToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_parse_token().fmt(f)
+    }
+}
+
+/// Copies parse trees into owned ones. Trees with the same body share one 
+/// copy of it.
+pub fn to_owned_parse_tokens(pts: &[ParseToken]) -> Vec<OwnedParseToken> {
+    let shared = &mut SharedStrs::default();
+    pts.iter().map(|pt| pt.to_owned_with(shared)).collect()
 }
 
 impl Display for ParseToken<'_> {
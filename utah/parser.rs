@@ -1,43 +1,357 @@
-use crate::ParseToken;
+use std::fmt::Display;
 
+use super::observer::*;
+use crate::{ParseToken, RustuckError, TagId, TagSet};
+
+const MAX_DEPTH: usize = 10_000;
+// How deeply the trees of operators may nest, so that combining them can't
+// overflow the stack, as for a `PrattParser`.
+const MAX_NESTING: usize = 256;
+
+/// A list of rules applied until none of them changes the tokens. The parser
+/// owns its rules, so one built from string literals is a `Parser<'static>` 
+/// that can be kept in a `static`.
+///
+/// The tags of the rules are interned when the parser is built, so matching
+/// compares ids.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Parser<'a> {
-    pub rules: &'a Vec<Rule<'a>>
+    // private, as the ids are interned from them once
+    rules: Vec<Rule<'a>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    ids: Vec<RuleIds>,
+    /// The tag of a complete top level token, e.g. "program". Tokens left
+    /// without it are reported by `diagnose`.
+    pub goal: Option<&'a str>,
+    /// Operators combined by precedence and associativity once the rules
+    /// are done, e.g. for arithmetic.
+    pub operators: Option<OperatorTable<'a>>
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(rules: Vec<Rule<'a>>) -> Self {
+        let ids = rules.iter().map(RuleIds::new).collect();
+        Parser { rules, ids, goal: None, operators: None }
+    }
+
+    pub fn rules(&self) -> &[Rule<'a>] {
+        &self.rules
+    }
+
+    pub fn into_rules(self) -> Vec<Rule<'a>> {
+        self.rules
+    }
+
+    pub fn with_goal(mut self, goal: &'a str) -> Self {
+        self.goal = Some(goal);
+        self
+    }
+
+    pub fn with_operators(mut self, operators: OperatorTable<'a>) -> Self {
+        self.operators = Some(operators);
+        self
+    }
+}
+
+/// A rule or the operators of a parser, as written in a `parser!`.
+#[doc(hidden)]
+pub enum ParserItem<'a> {
+    Rule(Rule<'a>),
+    Operators(OperatorTable<'a>)
+}
+
+impl<'a> From<Rule<'a>> for ParserItem<'a> {
+    fn from(rule: Rule<'a>) -> Self {
+        ParserItem::Rule(rule)
+    }
+}
+
+impl<'a> From<OperatorTable<'a>> for ParserItem<'a> {
+    fn from(operators: OperatorTable<'a>) -> Self {
+        ParserItem::Operators(operators)
+    }
 }
 
+/// A parser with the rules in order and the last operators.
+impl<'a> FromIterator<ParserItem<'a>> for Parser<'a> {
+    fn from_iter<I: IntoIterator<Item = ParserItem<'a>>>(iter: I) -> Self {
+        let (mut rules, mut operators) = (vec![], None);
+        for item in iter {
+            match item {
+                ParserItem::Rule(rule) => rules.push(rule),
+                ParserItem::Operators(table) => operators = Some(table)
+            }
+        }
+        let mut parser = Parser::new(rules);
+        parser.operators = operators;
+        parser
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rule<'a> {
-    pub matches: Vec<&'a str>,
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub matches: Vec<Match<'a>>,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned_all"))]
     pub tags: Vec<&'a str>,
     pub repeat: Option<usize>,
-    pub add_all: bool
+    pub add_all: bool,
+    /// Conditions on the tokens around a match, which are not part of it
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    pub context: Vec<Context<'a>>
 }
 
-impl Parser<'_> {
-    pub fn parse<'a>(&'a self, code: &mut Vec<ParseToken<'a>>, verbose: bool) {
-        self.parse_depth(code, verbose, 0);
+impl<'a> Rule<'a> {
+    /// A rule that combines the tokens it matches into one with the tags.
+    pub fn new(matches: Vec<Match<'a>>, tags: Vec<&'a str>) -> Self {
+        Rule { matches, tags, repeat: None, add_all: false, context: vec![] }
+    }
+
+    /// Repeats the match at the index, as in `rule!("a" "b" ; 1 ; "list")`.
+    pub fn with_repeat(mut self, index: usize) -> Self {
+        self.repeat = Some(index);
+        self
+    }
+
+    /// Adds the tags to every matched token instead of combining them, as in
+    /// `rule!("int" ;; "expression")`.
+    pub fn with_add_all(mut self) -> Self {
+        self.add_all = true;
+        self
     }
 
-    pub fn parse_depth<'a>(&'a self, code: &mut Vec<ParseToken<'a>>, verbose: bool, depth: usize) {
-        if depth > 10_000 {
-            panic!("Sanity check error: code parsed recursed too deeply");
+    /// Only matches when the token after the match has the tag.
+    pub fn followed_by(mut self, tag: &'a str) -> Self {
+        self.context.push(Context::FollowedBy(tag));
+        self
+    }
+
+    /// Only matches when the token after the match, if any, does not have
+    /// the tag, e.g. a name that is not followed by an `=`.
+    pub fn not_followed_by(mut self, tag: &'a str) -> Self {
+        self.context.push(Context::NotFollowedBy(tag));
+        self
+    }
+
+    /// Only matches when the token before the match has the tag.
+    pub fn preceded_by(mut self, tag: &'a str) -> Self {
+        self.context.push(Context::PrecededBy(tag));
+        self
+    }
+
+    /// Only matches when the token before the match, if any, does not have
+    /// the tag.
+    pub fn not_preceded_by(mut self, tag: &'a str) -> Self {
+        self.context.push(Context::NotPrecededBy(tag));
+        self
+    }
+}
+
+/// What a rule needs of one of the tokens it matches. A tag converts into
+/// a `Match::Tag`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Match<'a> {
+    /// A token with the tag
+    Tag(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned"))] &'a str),
+    /// A token with any of the tags
+    OneOf(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned_all"))] Vec<&'a str>),
+    /// A token without the tag
+    Not(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned"))] &'a str),
+    /// Any token
+    Any
+}
+
+impl<'a> Match<'a> {
+    /// Whether the token fits the match. `Not` and `Any` do not accept the
+    /// empty token that marks the end of the input.
+    pub fn accepts(&self, token: &ParseToken) -> bool {
+        let tags = &token.tags;
+        match self {
+            Match::Tag(tag) => tags.has(tag),
+            Match::OneOf(alternatives) => alternatives.iter().any(|tag| tags.has(tag)),
+            Match::Not(tag) => !token.location.is_empty() && !tags.has(tag),
+            Match::Any => !token.location.is_empty()
+        }
+    }
+
+    /// The tags of which every token it accepts has one, which is none for
+    /// `Not` and `Any`.
+    pub fn tags(&self) -> &[&'a str] {
+        match self {
+            Match::Tag(tag) => std::slice::from_ref(tag),
+            Match::OneOf(alternatives) => alternatives,
+            Match::Not(_) | Match::Any => &[]
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Match<'a> {
+    fn from(tag: &'a str) -> Self {
+        Match::Tag(tag)
+    }
+}
+
+// A match with its tags interned.
+#[derive(Debug, Clone)]
+enum MatchIds {
+    Tag(TagId),
+    OneOf(Vec<TagId>),
+    Not(TagId),
+    Any
+}
+
+impl MatchIds {
+    fn new(m: &Match<'_>) -> Self {
+        match m {
+            Match::Tag(tag) => MatchIds::Tag(TagId::intern(tag)),
+            Match::OneOf(alternatives) => MatchIds::OneOf(alternatives.iter().map(|tag| TagId::intern(tag)).collect()),
+            Match::Not(tag) => MatchIds::Not(TagId::intern(tag)),
+            Match::Any => MatchIds::Any
+        }
+    }
+
+    // As `Match::accepts`.
+    fn accepts(&self, token: &ParseToken) -> bool {
+        let tags = &token.tags;
+        match self {
+            MatchIds::Tag(tag) => tags.contains(*tag),
+            MatchIds::OneOf(alternatives) => alternatives.iter().any(|&tag| tags.contains(tag)),
+            MatchIds::Not(tag) => !token.location.is_empty() && !tags.contains(*tag),
+            MatchIds::Any => !token.location.is_empty()
+        }
+    }
+}
+
+// The tags of a rule, interned: those of its matches, of its context, in
+// order, and those it gives.
+#[derive(Debug, Clone)]
+struct RuleIds {
+    matches: Vec<MatchIds>,
+    context: Vec<TagId>,
+    tags: Vec<TagId>
+}
+
+impl RuleIds {
+    fn new(rule: &Rule<'_>) -> Self {
+        RuleIds {
+            matches: rule.matches.iter().map(MatchIds::new).collect(),
+            context: rule.context.iter().map(|context| TagId::intern(context.tag())).collect(),
+            tags: rule.tags.iter().map(|tag| TagId::intern(tag)).collect()
+        }
+    }
+}
+
+/// Writes the match as in a `rule!`: `"int"`, `"int" | "decimal"`, `!"ws"`
+/// or `_`.
+impl Display for Match<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Match::Tag(tag) => write!(f, "{tag:?}"),
+            Match::OneOf(alternatives) => {
+                let alternatives: Vec<String> = alternatives.iter().map(|tag| format!("{tag:?}")).collect();
+                write!(f, "{}", alternatives.join(" | "))
+            }
+            Match::Not(tag) => write!(f, "!{tag:?}"),
+            Match::Any => write!(f, "_")
+        }
+    }
+}
+
+/// A condition on the token just after or just before the match of a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Context<'a> {
+    FollowedBy(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned"))] &'a str),
+    NotFollowedBy(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned"))] &'a str),
+    PrecededBy(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned"))] &'a str),
+    NotPrecededBy(#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::serialize::interned"))] &'a str)
+}
+
+impl<'a> Context<'a> {
+    /// The name of the `Rule` method that adds the condition.
+    pub fn method(&self) -> &'static str {
+        match self {
+            Context::FollowedBy(_) => "followed_by",
+            Context::NotFollowedBy(_) => "not_followed_by",
+            Context::PrecededBy(_) => "preceded_by",
+            Context::NotPrecededBy(_) => "not_preceded_by"
+        }
+    }
+
+    pub fn tag(&self) -> &'a str {
+        match *self {
+            Context::FollowedBy(tag) | Context::NotFollowedBy(tag) | Context::PrecededBy(tag) | Context::NotPrecededBy(tag) => tag
         }
+    }
 
-        let mut changed = false;
+    /// Whether the condition holds for a match of the tokens in `start..end`.
+    pub fn holds(&self, code: &[ParseToken<'_>], start: usize, end: usize) -> bool {
+        self.holds_for(TagId::intern(self.tag()), code, start, end)
+    }
 
-        for rule in self.rules {
-            rule.traverse(code, &mut changed, verbose);
+    // As `holds`, with the tag interned.
+    fn holds_for(&self, tag: TagId, code: &[ParseToken<'_>], start: usize, end: usize) -> bool {
+        let before = start.checked_sub(1).and_then(|i| code.get(i));
+        let has = |token: Option<&ParseToken>| token.is_some_and(|token| token.tags.contains(tag));
+        match self {
+            Context::FollowedBy(_) => has(code.get(end)),
+            Context::NotFollowedBy(_) => !has(code.get(end)),
+            Context::PrecededBy(_) => has(before),
+            Context::NotPrecededBy(_) => !has(before)
         }
+    }
+}
 
-        if changed { 
-            if verbose {
-                println!("Entering depth {}", depth + 1);
+impl Parser<'_> {
+    pub fn parse(&self, code: &mut Vec<ParseToken<'_>>, observer: &mut dyn ParseObserver) -> Result<(), RustuckError> {
+        self.parse_depth(code, observer, 0)
+    }
+
+    /// Runs passes, counting from `depth`, until one changes nothing. A pass
+    /// that changes something combines tokens or adds a tag to one, so valid
+    /// input needs at most a pass per token and tag of a rule, and the limit
+    /// on passes is never lower than that.
+    pub fn parse_depth(&self, code: &mut Vec<ParseToken<'_>>, observer: &mut dyn ParseObserver, mut depth: usize) -> Result<(), RustuckError> {
+        let tags: usize = self.rules.iter().map(|rule| rule.tags.len()).sum();
+        let limit = MAX_DEPTH.max(code.len().saturating_mul(tags + 1));
+        loop {
+            if depth > limit {
+                return Err(RustuckError::RecursionLimit { depth });
+            }
+
+            observer.observe(&ParseEvent::PassStarted { depth });
+            let mut changed = false;
+
+            for (rule, ids) in self.rules.iter().zip(&self.ids) {
+                rule.traverse_ids(ids, code, &mut changed, observer);
             }
-            self.parse_depth(code, verbose, depth + 1); 
+            if let (false, Some(operators)) = (changed, &self.operators) {
+                operators.reduce(code, &mut changed, false, observer)?;
+                if !changed {
+                    operators.reduce(code, &mut changed, true, observer)?;
+                }
+            }
+
+            if !changed {
+                return Ok(());
+            }
+            depth += 1;
         }
     }
 }
 
 impl Rule<'_> {
-    pub fn traverse<'a>(&'a self, code: &mut Vec<ParseToken<'a>>, changed: &mut bool, verbose: bool) {
+    /// Combines, or adds the tags to, every match of the rule, as one pass of
+    /// a parser does. A parser interns the tags of its rules once, while this
+    /// interns them on every call.
+    pub fn traverse(&self, code: &mut Vec<ParseToken<'_>>, changed: &mut bool, observer: &mut dyn ParseObserver) {
+        self.traverse_ids(&RuleIds::new(self), code, changed, observer);
+    }
+
+    fn traverse_ids(&self, ids: &RuleIds, code: &mut Vec<ParseToken<'_>>, changed: &mut bool, observer: &mut dyn ParseObserver) {
         let mut start_index: usize = 0;
 
         'outer: while start_index < code.len() {
@@ -53,11 +367,8 @@ impl Rule<'_> {
                     }
                     break;
                 }
-                if !code[parse_token_index + start_index].tags.contains(&self.matches[index_in_rule]) {
+                if !ids.matches[index_in_rule].accepts(&code[parse_token_index + start_index]) {
                     if Some(index_in_rule) == self.repeat {
-                        if verbose {
-                            println!("Matched the * index.");
-                        }
                         index_in_rule += 1;
                         if index_in_rule >= self.matches.len() {
                             break;
@@ -76,53 +387,278 @@ impl Rule<'_> {
                 parse_token_index += 1;
             }
 
-            if !works {
+            // a lone repeated match that matched nothing has nothing to combine
+            if !works || parse_token_index == 0
+                || !self.context.iter().zip(&ids.context)
+                    .all(|(context, &tag)| context.holds_for(tag, code, start_index, start_index + parse_token_index)) {
                 start_index += 1;
                 continue 'outer;
             }
             
+            observer.observe(&ParseEvent::RuleMatched { 
+                rule: self, 
+                start: start_index, 
+                end: start_index + parse_token_index 
+            });
             if self.add_all {
-                self.add_all(code, start_index, start_index + parse_token_index, changed);
+                self.add_all(ids, code, start_index, start_index + parse_token_index, changed, observer);
             }
             else {
-                self.combine(code, start_index, start_index + parse_token_index, changed);
+                combine(ids, code, start_index, start_index + parse_token_index, changed, observer);
             }
 
             start_index += 1;
         }
     }
 
-    pub fn combine<'a>(&'a self, code: &mut Vec<ParseToken<'a>>, start_index: usize, end_index: usize, changed: &mut bool) {
-                code[start_index] = ParseToken {
-                    location: code[start_index].location.start..code[end_index - 1].location.end,
-                    body: code[0].body,
-                    children: code[start_index..end_index].to_vec(),
-                    tags: self.tags.clone(),
-                    ..code[start_index]
-                };
-                for i in (start_index + 1)..end_index {
-                    if i >= code.len() {
-                        continue;
-                    }
-
-                    code.remove(i);
-                    *changed = true;
-                }
-    }
-
-    pub fn add_all<'a>(&'a self, code: &mut Vec<ParseToken<'a>>, start_index: usize, end_index: usize, changed: &mut bool) {
+    fn add_all(&self, ids: &RuleIds, code: &mut [ParseToken<'_>], start_index: usize, end_index: usize, changed: &mut bool, observer: &mut dyn ParseObserver) {
             for pt in start_index..end_index {
                 if pt >= code.len() {
                     continue;
                 }
 
-                for t in &self.tags {
-                    if !code[pt].tags.contains(t) {
-                        code[pt].tags.push(t);
+                for (t, &id) in self.tags.iter().zip(&ids.tags) {
+                    if code[pt].tags.insert(id) {
                         *changed = true;
-                        println!("{}", code[pt]);
+                        observer.observe(&ParseEvent::TagAdded { tag: t, token: &code[pt] });
                     }
                 }
             }
     }
-}
\ No newline at end of file
+}
+
+fn combine(ids: &RuleIds, code: &mut Vec<ParseToken<'_>>, start_index: usize, end_index: usize, changed: &mut bool, observer: &mut dyn ParseObserver) {
+                // the children are moved rather than cloned, as trees can be
+                // as deep as the input is long
+                let children: Vec<ParseToken> = code.drain(start_index..end_index).collect();
+                let (first, last) = (&children[0], &children[children.len() - 1]);
+                let token = ParseToken {
+                    location: first.location.start..last.location.end,
+                    body: first.body,
+                    tags: TagSet::from_iter(ids.tags.iter().copied()),
+                    line: first.line,
+                    char: first.char,
+                    file: first.file,
+                    children
+                };
+                if token.children.len() > 1 {
+                    *changed = true;
+                }
+                code.insert(start_index, token);
+                observer.observe(&ParseEvent::TokensCombined { token: &code[start_index] });
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Fixity {
+    InfixLeft,
+    InfixRight,
+    Prefix,
+    Postfix
+}
+
+// What an operator after an operand does: ends it, or takes another operand
+// whose operators bind at least `next` tightly.
+enum Trailing {
+    Postfix,
+    Infix { next: u32 }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Operator<'a> {
+    /// The tag of the operator tokens
+    pub tag: &'a str,
+    pub fixity: Fixity,
+    /// Operators with a higher precedence bind tighter
+    pub precedence: u32,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    id: TagId
+}
+
+/// Operators of the expressions of a parser, which combine operands and
+/// operators into trees by precedence and associativity instead of by the
+/// order of the rules:
+///
+/// ```
+/// # use rustuck::*;
+/// let operators = OperatorTable::new("expression")
+///     .infixl(6, &["+", "-"])
+///     .infixl(7, &["*", "/"])
+///     .infixr(8, &["^"])
+///     .prefix(9, &["-"]);
+/// ```
+///
+/// Once no rule changes the tokens, every run of operands and operators
+/// between other tokens is combined into a single token with the operand
+/// tag, with a child token for each operator and its operands, in order, as
+/// a rule like `rule!("expression" "+" "expression" ; "expression")` would
+/// make. A run that ends in an operator is left alone until the tokens after
+/// it are combined into an operand. So is a run that starts with a prefix
+/// operator after a token that is not an operator, which may still end up
+/// an operand, until nothing else can change.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct OperatorTable<'a> {
+    // private, as the tags are interned once
+    operand: &'a str,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    operand_id: TagId,
+    operators: Vec<Operator<'a>>
+}
+
+impl<'a> OperatorTable<'a> {
+    pub fn new(operand: &'a str) -> Self {
+        OperatorTable { operand, operand_id: TagId::intern(operand), operators: vec![] }
+    }
+
+    /// The tag of the operands, and of the tokens the operators make.
+    pub fn operand(&self) -> &'a str {
+        self.operand
+    }
+
+    pub fn operators(&self) -> &[Operator<'a>] {
+        &self.operators
+    }
+
+    /// Left associative infix operators: `1 - 2 - 3` is `(1 - 2) - 3`.
+    pub fn infixl(self, precedence: u32, tags: &[&'a str]) -> Self {
+        self.with(Fixity::InfixLeft, precedence, tags)
+    }
+
+    /// Right associative infix operators: `2 ^ 3 ^ 4` is `2 ^ (3 ^ 4)`.
+    pub fn infixr(self, precedence: u32, tags: &[&'a str]) -> Self {
+        self.with(Fixity::InfixRight, precedence, tags)
+    }
+
+    pub fn prefix(self, precedence: u32, tags: &[&'a str]) -> Self {
+        self.with(Fixity::Prefix, precedence, tags)
+    }
+
+    pub fn postfix(self, precedence: u32, tags: &[&'a str]) -> Self {
+        self.with(Fixity::Postfix, precedence, tags)
+    }
+
+    pub(crate) fn with(mut self, fixity: Fixity, precedence: u32, tags: &[&'a str]) -> Self {
+        self.operators.extend(tags.iter().map(|&tag| Operator { tag, fixity, precedence, id: TagId::intern(tag) }));
+        self
+    }
+
+    fn find(&self, token: &ParseToken<'_>, fixity: Fixity) -> Option<&Operator<'a>> {
+        self.operators.iter().find(|op| op.fixity == fixity && token.tags.contains(op.id))
+    }
+
+    // The precedence of the operator a token is after an operand, and what
+    // it does there. Postfix operators come first.
+    fn trailing(&self, token: &ParseToken<'_>) -> Option<(u32, Trailing)> {
+        if let Some(op) = self.find(token, Fixity::Postfix) {
+            return Some((op.precedence, Trailing::Postfix));
+        }
+        if let Some(op) = self.find(token, Fixity::InfixLeft) {
+            return Some((op.precedence, Trailing::Infix { next: op.precedence.saturating_add(1) }));
+        }
+        self.find(token, Fixity::InfixRight).map(|op| (op.precedence, Trailing::Infix { next: op.precedence }))
+    }
+
+    fn is(&self, token: &ParseToken<'_>, fixities: &[Fixity]) -> bool {
+        self.operators.iter().any(|op| fixities.contains(&op.fixity) && token.tags.contains(op.id))
+    }
+
+    /// Combines each complete run of operands and operators into a tree,
+    /// including runs that start with a prefix operator after a token that
+    /// is not an operator if `eager`. Fails if a tree would nest too deep,
+    /// after which the tokens are incomplete, as after any failed parse.
+    pub(crate) fn reduce(&self, code: &mut Vec<ParseToken<'_>>, changed: &mut bool, eager: bool, observer: &mut dyn ParseObserver) -> Result<(), RustuckError> {
+        let all = [Fixity::InfixLeft, Fixity::InfixRight, Fixity::Prefix, Fixity::Postfix];
+        let mut start = 0;
+        while start < code.len() {
+            let deferred = !eager && start > 0
+                && self.is(&code[start], &[Fixity::Prefix])
+                && !self.is(&code[start - 1], &all);
+            match self.run(code, start) {
+                Some(end) if end > start + 1 && !deferred => {
+                    let mut tokens = code.drain(start..end).collect::<Vec<_>>().into_iter().peekable();
+                    let tree = self.climb(&mut tokens, 0, 0, observer)?;
+                    code.insert(start, tree);
+                    *changed = true;
+                    start += 1;
+                }
+                Some(end) => start = end.max(start + 1),
+                None => start += 1
+            }
+        }
+        Ok(())
+    }
+
+    // The end of the run of operands and operators from `start`, if it is
+    // complete.
+    fn run(&self, code: &[ParseToken<'_>], start: usize) -> Option<usize> {
+        let mut i = start;
+        loop {
+            while i < code.len() && self.is(&code[i], &[Fixity::Prefix]) {
+                i += 1;
+            }
+            if i >= code.len() || !code[i].tags.contains(self.operand_id) {
+                return None;
+            }
+            i += 1;
+            while i < code.len() && self.is(&code[i], &[Fixity::Postfix]) {
+                i += 1;
+            }
+            if i < code.len() && self.is(&code[i], &[Fixity::InfixLeft, Fixity::InfixRight]) {
+                i += 1;
+                continue;
+            }
+            return Some(i);
+        }
+    }
+
+    // Precedence climbing over a complete run.
+    fn climb<'t>(
+        &self,
+        tokens: &mut std::iter::Peekable<std::vec::IntoIter<ParseToken<'t>>>,
+        min_precedence: u32,
+        depth: usize,
+        observer: &mut dyn ParseObserver
+    ) -> Result<ParseToken<'t>, RustuckError> {
+        if depth == MAX_NESTING {
+            return Err(RustuckError::RecursionLimit { depth });
+        }
+        let first = tokens.next().unwrap();
+        let mut left = match self.find(&first, Fixity::Prefix) {
+            Some(op) => {
+                let operand = self.climb(tokens, op.precedence, depth + 1, observer)?;
+                self.combine(vec![first, operand], observer)
+            }
+            None => first
+        };
+        while let Some((precedence, trailing)) = tokens.peek().and_then(|token| self.trailing(token)) {
+            if precedence < min_precedence {
+                break;
+            }
+            let op_token = tokens.next().unwrap();
+            left = match trailing {
+                Trailing::Postfix => self.combine(vec![left, op_token], observer),
+                Trailing::Infix { next } => {
+                    let right = self.climb(tokens, next, depth + 1, observer)?;
+                    self.combine(vec![left, op_token, right], observer)
+                }
+            };
+        }
+        Ok(left)
+    }
+
+    fn combine<'t>(&self, children: Vec<ParseToken<'t>>, observer: &mut dyn ParseObserver) -> ParseToken<'t> {
+        let (first, last) = (&children[0], &children[children.len() - 1]);
+        let token = ParseToken {
+            location: first.location.start..last.location.end,
+            body: first.body,
+            tags: TagSet::from_iter([self.operand_id]),
+            line: first.line,
+            char: first.char,
+            file: first.file,
+            children
+        };
+        observer.observe(&ParseEvent::TokensCombined { token: &token });
+        token
+    }
+}
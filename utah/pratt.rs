@@ -0,0 +1,259 @@
+use std::collections::VecDeque;
+
+use crate::{ParseToken, RustuckError, TagId, TagSet};
+
+// How deeply expressions may nest, so that parsing can't overflow the stack,
+// even the 2 MiB of a spawned thread in a debug build.
+const MAX_DEPTH: usize = 256;
+
+type PrefixHandler<'a> = Box<dyn for<'t> Fn(&mut PrattInput<'_, 'a, 't>, ParseToken<'t>) -> Result<ParseToken<'t>, RustuckError> + 'a>;
+type InfixHandler<'a> = Box<dyn for<'t> Fn(&mut PrattInput<'_, 'a, 't>, ParseToken<'t>, ParseToken<'t>) -> Result<ParseToken<'t>, RustuckError> + 'a>;
+
+/// A top-down operator precedence parser for expressions, built from
+/// handlers keyed by the tags of the tokens they start at.
+///
+/// A prefix handler runs on a token where an expression starts, e.g. a
+/// literal, a prefix operator or an opening parenthesis. An infix handler
+/// runs on a token after an expression, e.g. an infix or postfix operator,
+/// and has a precedence: it takes the expression before it only if its
+/// precedence is at least the one the surrounding operator asked for.
+///
+/// ```
+/// # use rustuck::*;
+/// let expressions = PrattParser::new("expression")
+///     .atom("int")
+///     .group("(", ")")
+///     .prefix(9, &["-"])
+///     .infixl(6, &["+", "-"])
+///     .infixl(7, &["*"])
+///     .infixr(8, &["^"])
+///     .postfix(10, &["!"]);
+/// ```
+///
+/// The trees it builds are `ParseToken`s tagged with its tag, with a child
+/// for each token or expression they are made of, in order, like those of
+/// `rule!("expression" "+" "expression" ; "expression")`, so they can be
+/// parsed further by rules. The precedences and the order of the arguments
+/// are those of `OperatorTable`.
+pub struct PrattParser<'a> {
+    tag: &'a str,
+    // the tags are interned as the handlers are added
+    id: TagId,
+    prefix: Vec<(TagId, PrefixHandler<'a>)>,
+    infix: Vec<(TagId, u32, InfixHandler<'a>)>
+}
+
+impl<'a> PrattParser<'a> {
+    pub fn new(tag: &'a str) -> Self {
+        PrattParser { tag, id: TagId::intern(tag), prefix: vec![], infix: vec![] }
+    }
+
+    /// The tag of the expressions built.
+    pub fn tag(&self) -> &'a str {
+        self.tag
+    }
+
+    /// Runs `handler` on a token with the tag where an expression starts.
+    pub fn with_prefix(
+        mut self,
+        tag: &'a str,
+        handler: impl for<'t> Fn(&mut PrattInput<'_, 'a, 't>, ParseToken<'t>) -> Result<ParseToken<'t>, RustuckError> + 'a
+    ) -> Self {
+        self.prefix.push((TagId::intern(tag), Box::new(handler)));
+        self
+    }
+
+    /// Runs `handler` on a token with the tag after an expression, with the
+    /// expression, if `precedence` is high enough.
+    pub fn with_infix(
+        mut self,
+        tag: &'a str,
+        precedence: u32,
+        handler: impl for<'t> Fn(&mut PrattInput<'_, 'a, 't>, ParseToken<'t>, ParseToken<'t>) -> Result<ParseToken<'t>, RustuckError> + 'a
+    ) -> Self {
+        self.infix.push((TagId::intern(tag), precedence, Box::new(handler)));
+        self
+    }
+
+    /// A token with the tag is an expression by itself, and gets the tag of
+    /// expressions.
+    pub fn atom(self, tag: &'a str) -> Self {
+        self.with_prefix(tag, |input, mut token| {
+            token.tags.insert(input.parser.id);
+            Ok(token)
+        })
+    }
+
+    /// The tag opens an expression that a token with `close` ends.
+    pub fn group(self, open: &'a str, close: &'a str) -> Self {
+        self.with_prefix(open, move |input, token| {
+            let inner = input.expression(0)?;
+            let close = input.expect(close)?;
+            Ok(input.combine(vec![token, inner, close]))
+        })
+    }
+
+    pub fn prefix(self, precedence: u32, tags: &[&'a str]) -> Self {
+        tags.iter().fold(self, |parser, &tag| parser.with_prefix(tag, move |input, token| {
+            let operand = input.expression(precedence)?;
+            Ok(input.combine(vec![token, operand]))
+        }))
+    }
+
+    /// Left associative infix operators: `1 - 2 - 3` is `(1 - 2) - 3`.
+    pub fn infixl(self, precedence: u32, tags: &[&'a str]) -> Self {
+        tags.iter().fold(self, |parser, &tag| parser.with_infix(tag, precedence, move |input, left, token| {
+            let right = input.expression(precedence.saturating_add(1))?;
+            Ok(input.combine(vec![left, token, right]))
+        }))
+    }
+
+    /// Right associative infix operators: `2 ^ 3 ^ 4` is `2 ^ (3 ^ 4)`.
+    pub fn infixr(self, precedence: u32, tags: &[&'a str]) -> Self {
+        tags.iter().fold(self, |parser, &tag| parser.with_infix(tag, precedence, move |input, left, token| {
+            let right = input.expression(precedence)?;
+            Ok(input.combine(vec![left, token, right]))
+        }))
+    }
+
+    pub fn postfix(self, precedence: u32, tags: &[&'a str]) -> Self {
+        tags.iter().fold(self, |parser, &tag| {
+            parser.with_infix(tag, precedence, |input, left, token| Ok(input.combine(vec![left, token])))
+        })
+    }
+
+    /// Parses all of the tokens as a single expression.
+    pub fn parse<'t>(&self, tokens: Vec<ParseToken<'t>>) -> Result<ParseToken<'t>, RustuckError> {
+        let mut input = PrattInput { parser: self, tokens: tokens.into(), taken: None, depth: 0 };
+        let expression = input.expression(0)?;
+        match input.tokens.front() {
+            Some(token) => Err(unexpected("the end of the expression", token)),
+            None => Ok(expression)
+        }
+    }
+
+    /// Parses the expression that starts at `code[start]`, replacing its
+    /// tokens with the tree, and leaves the tokens after it in place. On
+    /// errors, the tokens are not changed.
+    pub fn parse_at(&self, code: &mut Vec<ParseToken<'_>>, start: usize) -> Result<(), RustuckError> {
+        if start > code.len() {
+            return Err(RustuckError::StartOutOfBounds { index: start, len: code.len() });
+        }
+        let tokens = code.drain(start..).collect();
+        let mut input = PrattInput { parser: self, tokens, taken: Some(vec![]), depth: 0 };
+        match input.expression(0) {
+            Ok(expression) => {
+                code.push(expression);
+                code.extend(input.tokens);
+                Ok(())
+            }
+            Err(e) => {
+                code.extend(input.taken.unwrap_or_default());
+                code.extend(input.tokens);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for PrattParser<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prefix: Vec<TagId> = self.prefix.iter().map(|(tag, _)| *tag).collect();
+        let infix: Vec<(TagId, u32)> = self.infix.iter().map(|(tag, precedence, _)| (*tag, *precedence)).collect();
+        f.debug_struct("PrattParser")
+            .field("tag", &self.tag)
+            .field("prefix", &prefix)
+            .field("infix", &infix)
+            .finish()
+    }
+}
+
+/// The tokens left to parse, which handlers read their operands from.
+pub struct PrattInput<'p, 'a, 't> {
+    pub parser: &'p PrattParser<'a>,
+    tokens: VecDeque<ParseToken<'t>>,
+    // copies of the tokens taken so far, to put back if parsing fails
+    taken: Option<Vec<ParseToken<'t>>>,
+    // the number of expressions being parsed
+    depth: usize
+}
+
+impl<'t> PrattInput<'_, '_, 't> {
+    /// Parses an expression whose infix operators all have at least
+    /// `min_precedence`.
+    pub fn expression(&mut self, min_precedence: u32) -> Result<ParseToken<'t>, RustuckError> {
+        if self.depth == MAX_DEPTH {
+            return Err(RustuckError::RecursionLimit { depth: self.depth });
+        }
+        self.depth += 1;
+        let expression = self.nested_expression(min_precedence);
+        self.depth -= 1;
+        expression
+    }
+
+    fn nested_expression(&mut self, min_precedence: u32) -> Result<ParseToken<'t>, RustuckError> {
+        let parser = self.parser;
+        let Some(token) = self.next_token() else {
+            return Err(RustuckError::UnexpectedEnd { expected: "an expression".to_string() });
+        };
+        let Some((_, handler)) = parser.prefix.iter().find(|&&(tag, _)| token.tags.contains(tag)) else {
+            return Err(unexpected("an expression", &token));
+        };
+        let mut left = handler(self, token)?;
+
+        while let Some(next) = self.tokens.front() {
+            let Some((_, _, handler)) = parser.infix.iter()
+                .find(|&&(tag, precedence, _)| precedence >= min_precedence && next.tags.contains(tag)) else {
+                break;
+            };
+            let token = self.next_token().unwrap();
+            left = handler(self, left, token)?;
+        }
+        Ok(left)
+    }
+
+    pub fn peek(&self) -> Option<&ParseToken<'t>> {
+        self.tokens.front()
+    }
+
+    pub fn next_token(&mut self) -> Option<ParseToken<'t>> {
+        let token = self.tokens.pop_front()?;
+        if let Some(taken) = &mut self.taken {
+            taken.push(token.clone());
+        }
+        Some(token)
+    }
+
+    /// The next token, if it has the tag.
+    pub fn expect(&mut self, tag: &str) -> Result<ParseToken<'t>, RustuckError> {
+        let expected = format!("{tag:?}");
+        match self.next_token() {
+            Some(token) if token.tags.has(tag) => Ok(token),
+            Some(token) => Err(unexpected(&expected, &token)),
+            None => Err(RustuckError::UnexpectedEnd { expected })
+        }
+    }
+
+    /// A token made of the children, with the tag of expressions.
+    pub fn combine(&self, children: Vec<ParseToken<'t>>) -> ParseToken<'t> {
+        let (first, last) = (&children[0], &children[children.len() - 1]);
+        ParseToken {
+            location: first.location.start..last.location.end,
+            body: first.body,
+            tags: TagSet::from_iter([self.parser.id]),
+            line: first.line,
+            char: first.char,
+            file: first.file,
+            children
+        }
+    }
+}
+
+fn unexpected(expected: &str, token: &ParseToken<'_>) -> RustuckError {
+    RustuckError::UnexpectedToken {
+        expected: expected.to_string(),
+        found: token.content().to_string(),
+        line: token.line,
+        char: token.char
+    }
+}